@@ -36,12 +36,30 @@
 //! ```text
 //! cargo add annotate-snippets --dev --feature testing-colors
 //! ```
+//! - `std` (default) - Adds the APIs that need the standard library, like
+//! `Snippet::origin_path`. Without it, the crate only depends on `alloc`.
+//! - `termcolor` - Adds `Renderer::render_termcolor` to render through a
+//! [`termcolor::WriteColor`](https://docs.rs/termcolor/latest/termcolor/trait.WriteColor.html)
+//! - `serde` - Adds `Renderer::render_json` to render as JSON, like rustc's
+//! `--error-format=json`
+//! - `is-terminal` - Makes `Renderer::from_env` check whether stdout is a
+//! terminal
+//! - `svg` - Adds `Renderer::render_svg` to render as an SVG image
+//! - `lsp` - Adds the `lsp` module to convert [`Message`]s into the shape of
+//! LSP diagnostics
 
+#![no_std]
 #![cfg_attr(docsrs, feature(doc_auto_cfg))]
 #![warn(clippy::print_stderr)]
 #![warn(clippy::print_stdout)]
 #![warn(missing_debug_implementations)]
 
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+#[cfg(feature = "lsp")]
+pub mod lsp;
 pub mod renderer;
 mod snippet;
 
@@ -0,0 +1,194 @@
+//! Converting [`Message`]s into the shape of [LSP] diagnostics
+//!
+//! This doesn't depend on a particular LSP crate; the plain structs here are
+//! meant to be mapped onto, e.g., `lsp_types::Diagnostic`.
+//!
+//! Like [`Renderer::annotate_kinds`][crate::Renderer::annotate_kinds],
+//! annotations of [`Level::Error`] are primary and all others are context.
+//!
+//! [LSP]: https://microsoft.github.io/language-server-protocol/
+
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::renderer::level_name;
+use crate::snippet::{Annotation, Level, Message, Snippet};
+
+/// A zero-based position in a document
+///
+/// The `character` is counted in UTF-16 code units, the default encoding of
+/// LSP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    pub line: usize,
+    pub character: usize,
+}
+
+/// A range in a document, excluding its `end`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+/// How severe a [`Diagnostic`] is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Error = 1,
+    Warning = 2,
+    Information = 3,
+    Hint = 4,
+}
+
+impl Severity {
+    /// The severity of `level`, if it has one
+    ///
+    /// [`Level::Info`] and [`Level::Note`] are [`Severity::Information`],
+    /// [`Level::Help`] is a [`Severity::Hint`]. Custom levels have no
+    /// severity.
+    pub fn from_level(level: Level) -> Option<Self> {
+        match level {
+            Level::Error => Some(Self::Error),
+            Level::Warning => Some(Self::Warning),
+            Level::Info | Level::Note => Some(Self::Information),
+            Level::Help => Some(Self::Hint),
+            Level::None | Level::Custom(..) => None,
+        }
+    }
+}
+
+/// A context annotation related to a [`Diagnostic`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RelatedInformation {
+    /// The [`Snippet::origin`] of the annotation
+    pub origin: Option<String>,
+    pub range: Range,
+    /// The label of the annotation, or the title of the footer it is in
+    pub message: String,
+}
+
+/// A diagnostic for a primary annotation of a [`Message`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Diagnostic {
+    /// The [`Snippet::origin`] of the primary annotation
+    pub origin: Option<String>,
+    pub range: Range,
+    pub severity: Option<Severity>,
+    /// The [`Message::id`]
+    pub code: Option<String>,
+    /// The title, followed by the label of the primary annotation and the
+    /// titles of footers, each on its own line
+    pub message: String,
+    pub related_information: Vec<RelatedInformation>,
+}
+
+/// Convert `msg` into a [`Diagnostic`] for each of its primary annotations
+///
+/// The other annotations of `msg` and its footers become the
+/// [`related_information`][Diagnostic::related_information] of each
+/// diagnostic. When `msg` has no primary annotations, its first annotation is
+/// used instead. A message without annotations has no place to be shown and
+/// gives no diagnostics.
+pub fn to_diagnostics(msg: &Message<'_>) -> Vec<Diagnostic> {
+    let annotations: Vec<(&Snippet<'_>, &Annotation<'_>)> = msg
+        .snippets
+        .iter()
+        .flat_map(|snippet| {
+            snippet
+                .annotations
+                .iter()
+                .map(move |annotation| (snippet, annotation))
+        })
+        .collect();
+    let mut primary: Vec<usize> = annotations
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, annotation))| annotation.level == Level::Error)
+        .map(|(idx, _)| idx)
+        .collect();
+    if primary.is_empty() && !annotations.is_empty() {
+        primary.push(0);
+    }
+
+    let mut related: Vec<_> = annotations
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| !primary.contains(idx))
+        .map(|(_, (snippet, annotation))| related_information(msg.title, snippet, annotation))
+        .collect();
+    let mut notes = String::new();
+    for footer in &msg.footer {
+        collect_footer(footer, &mut related, &mut notes);
+    }
+
+    primary
+        .into_iter()
+        .map(|idx| {
+            let (snippet, annotation) = annotations[idx];
+            let mut message = msg.title.to_owned();
+            if let Some(label) = &annotation.label {
+                message.push('\n');
+                message.push_str(label);
+            }
+            message.push_str(&notes);
+            Diagnostic {
+                origin: snippet.origin.as_deref().map(str::to_owned),
+                range: range(snippet, annotation),
+                severity: Severity::from_level(msg.level),
+                code: msg.id.map(str::to_owned),
+                message,
+                related_information: related.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Add the annotations of `footer` to `related` and its title to `notes`
+fn collect_footer(footer: &Message<'_>, related: &mut Vec<RelatedInformation>, notes: &mut String) {
+    notes.push('\n');
+    let level = level_name(footer.level);
+    if !level.is_empty() {
+        notes.push_str(level);
+        notes.push_str(": ");
+    }
+    notes.push_str(footer.title);
+    for snippet in &footer.snippets {
+        for annotation in &snippet.annotations {
+            related.push(related_information(footer.title, snippet, annotation));
+        }
+    }
+    for footer in &footer.footer {
+        collect_footer(footer, related, notes);
+    }
+}
+
+fn related_information(
+    title: &str,
+    snippet: &Snippet<'_>,
+    annotation: &Annotation<'_>,
+) -> RelatedInformation {
+    RelatedInformation {
+        origin: snippet.origin.as_deref().map(str::to_owned),
+        range: range(snippet, annotation),
+        message: annotation.label.as_deref().unwrap_or(title).to_owned(),
+    }
+}
+
+fn range(snippet: &Snippet<'_>, annotation: &Annotation<'_>) -> Range {
+    Range {
+        start: position(snippet, annotation.range.start),
+        end: position(snippet, annotation.range.end),
+    }
+}
+
+/// The zero-based position of `offset` in the document `snippet` is from
+fn position(snippet: &Snippet<'_>, offset: usize) -> Position {
+    let (line, before) = snippet.line_prefix(offset);
+    Position {
+        line: line.saturating_sub(1),
+        character: before.encode_utf16().count(),
+    }
+}
@@ -0,0 +1,100 @@
+//! Following what escape sequences, and the styles they are rendered from,
+//! do to the style of text
+
+use anstyle::{Ansi256Color, Color, Effects, RgbColor, Style};
+
+/// What an escape sequence of source text with
+/// [`Snippet::source_has_ansi`][crate::Snippet::source_has_ansi] does
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Sequence<'a> {
+    /// Set the style of the text that follows, from an SGR sequence
+    Style(Style),
+    /// Open an OSC 8 hyperlink, or close it with `None`
+    Hyperlink(Option<&'a str>),
+    /// Anything else, which is dropped
+    Other,
+}
+
+/// Read the escape `sequence`, applying it to the current `style`
+pub(crate) fn parse(sequence: &str, style: Style) -> Sequence<'_> {
+    let Some(rest) = sequence.strip_prefix('\x1b') else {
+        return Sequence::Other;
+    };
+    if let Some(params) = rest
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix('m'))
+    {
+        return Sequence::Style(apply_sgr(style, params));
+    }
+    if let Some(command) = rest.strip_prefix(']') {
+        let command = command
+            .find(['\x07', '\x1b'])
+            .map_or(command, |end| &command[..end]);
+        if let Some(link) = command.strip_prefix("8;") {
+            let uri = link.split_once(';').map_or("", |(_, uri)| uri);
+            return Sequence::Hyperlink((!uri.is_empty()).then_some(uri));
+        }
+    }
+    Sequence::Other
+}
+
+/// The style of text after `next` is rendered over `style`, as SGR sequences
+/// only ever add to the current style
+pub(crate) fn overlay(style: Style, next: &Style) -> Style {
+    let mut style = style.effects(style.get_effects() | next.get_effects());
+    if let Some(fg) = next.get_fg_color() {
+        style = style.fg_color(Some(fg));
+    }
+    if let Some(bg) = next.get_bg_color() {
+        style = style.bg_color(Some(bg));
+    }
+    if let Some(underline) = next.get_underline_color() {
+        style = style.underline_color(Some(underline));
+    }
+    style
+}
+
+fn apply_sgr(mut style: Style, params: &str) -> Style {
+    let mut params = params
+        .split(';')
+        .map(|param| param.parse::<u8>().unwrap_or(0));
+    while let Some(param) = params.next() {
+        style = match param {
+            0 => Style::new(),
+            1 => style | Effects::BOLD,
+            2 => style | Effects::DIMMED,
+            3 => style | Effects::ITALIC,
+            4 => style | Effects::UNDERLINE,
+            9 => style | Effects::STRIKETHROUGH,
+            30..=37 => style.fg_color(Some(ansi_color(param - 30))),
+            90..=97 => style.fg_color(Some(ansi_color(param - 90 + 8))),
+            38 => style.fg_color(extended_color(&mut params)),
+            39 => style.fg_color(None),
+            40..=47 => style.bg_color(Some(ansi_color(param - 40))),
+            100..=107 => style.bg_color(Some(ansi_color(param - 100 + 8))),
+            48 => style.bg_color(extended_color(&mut params)),
+            49 => style.bg_color(None),
+            _ => style,
+        };
+    }
+    style
+}
+
+/// One of the 16 colors of the terminal, with the bright ones from `8`
+fn ansi_color(index: u8) -> Color {
+    let color = Ansi256Color(index);
+    color.into_ansi().map_or(Color::Ansi256(color), Color::Ansi)
+}
+
+/// Parse the rest of a `38;5;n` or `38;2;r;g;b` color
+fn extended_color(params: &mut impl Iterator<Item = u8>) -> Option<Color> {
+    match params.next()? {
+        5 => Some(Color::Ansi256(Ansi256Color(params.next()?))),
+        2 => Some(Color::Rgb(RgbColor(
+            params.next()?,
+            params.next()?,
+            params.next()?,
+        ))),
+        _ => None,
+    }
+}
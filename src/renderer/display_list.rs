@@ -32,14 +32,20 @@
 //!
 //! The above snippet has been built out of the following structure:
 use crate::snippet;
-use std::cmp::{max, min};
-use std::fmt::{Display, Write};
-use std::ops::Range;
-use std::{cmp, fmt};
-
-use crate::renderer::{stylesheet::Stylesheet, Margin, Style, DEFAULT_TERM_WIDTH};
-
-const ANONYMIZED_LINE_NUM: &str = "LL";
+use crate::snippet::MultiLineLabel;
+use alloc::borrow::{Cow, ToOwned};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::cmp::{max, min};
+use core::fmt::{Display, Write};
+use core::ops::Range;
+use core::{cmp, fmt, ptr};
+
+use crate::renderer::{HighlightStyle, Margin, Renderer, Style, Stylesheet, DEFAULT_TERM_WIDTH};
+
+const CLIPPED_MARK: char = '…';
+const COMBINING_LOW_LINE: char = '\u{0332}';
 const ERROR_TXT: &str = "error";
 const HELP_TXT: &str = "help";
 const INFO_TXT: &str = "info";
@@ -49,13 +55,13 @@ const WARNING_TXT: &str = "warning";
 /// List of lines to be displayed.
 pub(crate) struct DisplayList<'a> {
     pub(crate) body: Vec<DisplaySet<'a>>,
-    pub(crate) stylesheet: &'a Stylesheet,
-    pub(crate) anonymized_line_numbers: bool,
+    pub(crate) renderer: &'a Renderer,
 }
 
 impl<'a> PartialEq for DisplayList<'a> {
     fn eq(&self, other: &Self) -> bool {
-        self.body == other.body && self.anonymized_line_numbers == other.anonymized_line_numbers
+        self.body == other.body
+            && self.renderer.anonymized_line_numbers == other.renderer.anonymized_line_numbers
     }
 }
 
@@ -63,26 +69,37 @@ impl<'a> fmt::Debug for DisplayList<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("DisplayList")
             .field("body", &self.body)
-            .field("anonymized_line_numbers", &self.anonymized_line_numbers)
+            .field(
+                "anonymized_line_numbers",
+                &self.renderer.anonymized_line_numbers,
+            )
             .finish()
     }
 }
 
 impl<'a> Display for DisplayList<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let lineno_width = self.body.iter().fold(0, |max, set| {
-            set.display_lines.iter().fold(max, |max, line| match line {
-                DisplayLine::Source { lineno, .. } => cmp::max(lineno.unwrap_or(0), max),
-                _ => max,
-            })
-        });
-        let lineno_width = if lineno_width == 0 {
-            lineno_width
-        } else if self.anonymized_line_numbers {
-            ANONYMIZED_LINE_NUM.len()
-        } else {
-            ((lineno_width as f64).log10().floor() as usize) + 1
-        };
+        self.write_to(f)
+    }
+}
+
+impl<'a> DisplayList<'a> {
+    /// Write the rendered list to `f`, with its styles as calls on it
+    pub(crate) fn write_to(&self, f: &mut dyn StyledWrite) -> fmt::Result {
+        if !self.renderer.trailing_newline {
+            return self.format_body(f);
+        }
+        let mut rendered = Recording::default();
+        self.format_body(&mut rendered)?;
+        rendered.replay(f)?;
+        if !rendered.ends_with_newline() {
+            f.write_str(self.renderer.line_ending.as_str())?;
+        }
+        Ok(())
+    }
+
+    fn format_body(&self, f: &mut dyn StyledWrite) -> fmt::Result {
+        let (lineno_width, local_lineno_width) = self.lineno_widths();
         let inline_marks_width = self.body.iter().fold(0, |max, set| {
             set.display_lines.iter().fold(max, |max, line| match line {
                 DisplayLine::Source { inline_marks, .. } => cmp::max(inline_marks.len(), max),
@@ -92,7 +109,14 @@ impl<'a> Display for DisplayList<'a> {
 
         let mut count_offset = 0;
         for set in self.body.iter() {
-            self.format_set(set, lineno_width, inline_marks_width, count_offset, f)?;
+            self.format_set(
+                set,
+                lineno_width,
+                local_lineno_width,
+                inline_marks_width,
+                count_offset,
+                f,
+            )?;
             count_offset += set.display_lines.len();
         }
         Ok(())
@@ -101,27 +125,133 @@ impl<'a> Display for DisplayList<'a> {
 
 impl<'a> DisplayList<'a> {
     pub(crate) fn new(
-        message: snippet::Message<'a>,
-        stylesheet: &'a Stylesheet,
-        anonymized_line_numbers: bool,
-        term_width: usize,
+        mut message: snippet::Message<'a>,
+        renderer: &'a Renderer,
     ) -> DisplayList<'a> {
-        let body = format_message(message, term_width, anonymized_line_numbers, true);
+        if let Some(focus) = renderer.focus {
+            dim_annotations(&mut message, focus, &mut 0);
+        }
+        let body = format_message(message, renderer, None, &mut None);
 
-        Self {
-            body,
-            stylesheet,
-            anonymized_line_numbers,
+        Self { body, renderer }
+    }
+
+    /// Leave out the title of the message, keeping only its snippets and
+    /// footers
+    pub(crate) fn remove_title(&mut self) {
+        if let Some(first) = self.body.first_mut() {
+            first.display_lines.remove(0);
+            if first.display_lines.is_empty() {
+                self.body.remove(0);
+            }
+        }
+    }
+
+    /// Only the origin header of `snippet`, if it has one
+    pub(crate) fn origin(
+        snippet: snippet::Snippet<'a>,
+        renderer: &'a Renderer,
+    ) -> Option<DisplayList<'a>> {
+        let mut set = format_snippet(fold_prefix_suffix(snippet).0, true, false, renderer);
+        match set.display_lines.first() {
+            Some(DisplayLine::Raw(DisplayRawLine::Origin { .. })) => {
+                set.display_lines.truncate(1);
+            }
+            _ => return None,
+        }
+        Some(Self {
+            body: vec![set],
+            renderer,
+        })
+    }
+
+    /// The path and `(line, column)` the origin header of `snippet` points at
+    pub(crate) fn origin_position(
+        snippet: snippet::Snippet<'a>,
+        renderer: &Renderer,
+    ) -> Option<(Cow<'a, str>, Option<Position>)> {
+        let set = format_snippet(fold_prefix_suffix(snippet).0, true, false, renderer);
+        match set.display_lines.into_iter().next() {
+            Some(DisplayLine::Raw(DisplayRawLine::Origin { path, pos, .. })) => Some((path, pos)),
+            _ => None,
+        }
+    }
+
+    /// The width of the line numbers, and of the line numbers relative to
+    /// each snippet within them when rendering with [`Renderer::dual_line_numbers`]
+    pub(crate) fn lineno_widths(&self) -> (usize, usize) {
+        let lineno_width = self.body.iter().fold(0, |max, set| {
+            let lineno = set.display_lines.iter().fold(0, |max, line| match line {
+                DisplayLine::Source { lineno, .. } => cmp::max(lineno.unwrap_or(0), max),
+                _ => max,
+            });
+            let lineno_width = if lineno == 0 {
+                0
+            } else if set.anonymized_line_numbers {
+                display_width(self.renderer.anonymized_line_num, self.renderer)
+            } else {
+                lineno.to_string().len()
+            };
+            cmp::max(lineno_width, max)
+        });
+        let local_lineno_width = if self.renderer.dual_line_numbers {
+            self.body
+                .iter()
+                .filter(|set| !set.anonymized_line_numbers)
+                .fold(0, |max, set| {
+                    let lineno = set.display_lines.iter().fold(0, |max, line| match line {
+                        DisplayLine::Source {
+                            lineno: Some(lineno),
+                            ..
+                        } => cmp::max(set.local_lineno(*lineno), max),
+                        _ => max,
+                    });
+                    if lineno == 0 {
+                        max
+                    } else {
+                        cmp::max(lineno.to_string().len(), max)
+                    }
+                })
+        } else {
+            0
+        };
+        let lineno_width = if local_lineno_width == 0 {
+            lineno_width
+        } else {
+            lineno_width + 1 + local_lineno_width
+        };
+        (lineno_width, local_lineno_width)
+    }
+
+    /// The line numbers of the source lines that were trimmed to fit the
+    /// terminal width.
+    pub(crate) fn truncated_lines(&self) -> Vec<usize> {
+        let mut lines = vec![];
+        for set in &self.body {
+            for line in &set.display_lines {
+                if let DisplayLine::Source {
+                    lineno: Some(lineno),
+                    line: DisplaySourceLine::Content { text, .. },
+                    ..
+                } = line
+                {
+                    if set.is_truncated(text, self.renderer) {
+                        lines.push(*lineno);
+                    }
+                }
+            }
         }
+        lines
     }
 
     fn format_set(
         &self,
         set: &DisplaySet<'_>,
         lineno_width: usize,
+        local_lineno_width: usize,
         inline_marks_width: usize,
         count_offset: usize,
-        f: &mut fmt::Formatter<'_>,
+        f: &mut dyn StyledWrite,
     ) -> fmt::Result {
         let body_len = self
             .body
@@ -129,16 +259,17 @@ impl<'a> DisplayList<'a> {
             .map(|set| set.display_lines.len())
             .sum::<usize>();
         for (i, line) in set.display_lines.iter().enumerate() {
+            format_repeat_char(' ', self.renderer.indent, f)?;
             set.format_line(
                 line,
                 lineno_width,
+                local_lineno_width,
                 inline_marks_width,
-                self.stylesheet,
-                self.anonymized_line_numbers,
+                self.renderer,
                 f,
             )?;
             if i + count_offset + 1 < body_len {
-                f.write_char('\n')?;
+                f.write_str(self.renderer.line_ending.as_str())?;
             }
         }
         Ok(())
@@ -149,28 +280,56 @@ impl<'a> DisplayList<'a> {
 pub(crate) struct DisplaySet<'a> {
     pub(crate) display_lines: Vec<DisplayLine<'a>>,
     pub(crate) margin: Margin,
+    pub(crate) anonymized_line_numbers: bool,
+    /// The line number of the first line of the snippet, before folding
+    pub(crate) excerpt_line_start: usize,
+    /// Whether the source contains ANSI escape sequences to be passed through
+    pub(crate) source_has_ansi: bool,
+    /// The offset of the source in that of the snippet, before folding
+    pub(crate) source_offset: usize,
+    /// The column of the end of the right-most span, to align labels after
+    /// when rendering with [`Renderer::side_labels`]
+    pub(crate) side_label_col: Option<usize>,
+    /// Whether the snippet is of another file than the first snippet of the
+    /// message, see [`Renderer::secondary_line_no`]
+    pub(crate) secondary_file: bool,
 }
 
 impl<'a> DisplaySet<'a> {
+    /// The style of the line numbers and the gutter of the snippet
+    fn line_no_style<'r>(&self, renderer: &'r Renderer) -> &'r Style {
+        if self.secondary_file {
+            renderer.stylesheet.secondary_line_no_style()
+        } else {
+            &renderer.stylesheet.line_no
+        }
+    }
+
+    /// The line number relative to the start of the snippet
+    fn local_lineno(&self, lineno: usize) -> usize {
+        (lineno + 1).saturating_sub(self.excerpt_line_start)
+    }
+
+    fn is_truncated(&self, text: &str, renderer: &Renderer) -> bool {
+        let line_len = normalize_whitespace(text, renderer.expanded_tab_width()).len();
+        self.margin.was_cut_left() || self.margin.was_cut_right(line_len)
+    }
+
     fn format_label(
         &self,
         label: &[DisplayTextFragment<'_>],
-        stylesheet: &Stylesheet,
-        f: &mut fmt::Formatter<'_>,
+        renderer: &Renderer,
+        f: &mut dyn StyledWrite,
     ) -> fmt::Result {
-        let emphasis_style = stylesheet.emphasis();
+        let emphasis_style = &renderer.stylesheet.emphasis;
 
         for fragment in label {
             match fragment.style {
-                DisplayTextStyle::Regular => fragment.content.fmt(f)?,
+                DisplayTextStyle::Regular => f.write_str(&fragment.content)?,
                 DisplayTextStyle::Emphasis => {
-                    write!(
-                        f,
-                        "{}{}{}",
-                        emphasis_style.render(),
-                        fragment.content,
-                        emphasis_style.render_reset()
-                    )?;
+                    f.set_style(emphasis_style)?;
+                    f.write_str(&fragment.content)?;
+                    f.reset_style(emphasis_style)?;
                 }
             }
         }
@@ -182,41 +341,60 @@ impl<'a> DisplaySet<'a> {
         annotation: &Annotation<'_>,
         continuation: bool,
         in_source: bool,
-        stylesheet: &Stylesheet,
-        f: &mut fmt::Formatter<'_>,
+        dimmed: bool,
+        renderer: &Renderer,
+        f: &mut dyn StyledWrite,
     ) -> fmt::Result {
-        let color = get_annotation_style(&annotation.annotation_type, stylesheet);
+        let color = source_annotation_style(&annotation.annotation_type, dimmed, renderer);
         let formatted_len = if let Some(id) = &annotation.id {
-            2 + id.len() + annotation_type_len(&annotation.annotation_type)
+            2 + id.len() + annotation_type_len(&annotation.annotation_type, renderer)
         } else {
-            annotation_type_len(&annotation.annotation_type)
+            annotation_type_len(&annotation.annotation_type, renderer)
         };
 
         if continuation {
             format_repeat_char(' ', formatted_len + 2, f)?;
-            return self.format_label(&annotation.label, stylesheet, f);
+            return self.format_label(&annotation.label, renderer, f);
         }
         if formatted_len == 0 {
-            self.format_label(&annotation.label, stylesheet, f)
+            self.format_label(&annotation.label, renderer, f)
         } else {
-            write!(f, "{}", color.render())?;
+            // Terminals that get no styles may not understand links either
+            let url = annotation
+                .id
+                .and(annotation.id_url)
+                .filter(|_| !renderer.stylesheet.is_plain());
+            let link_level = renderer.link_level && url.is_some();
+            if link_level {
+                format_hyperlink_start(url, f)?;
+            }
+            f.set_style(color)?;
             format_annotation_type(&annotation.annotation_type, f)?;
             if let Some(id) = &annotation.id {
+                if !link_level {
+                    format_hyperlink_start(url, f)?;
+                }
                 f.write_char('[')?;
                 f.write_str(id)?;
                 f.write_char(']')?;
+                if !link_level {
+                    format_hyperlink_end(url, f)?;
+                }
+            }
+            f.reset_style(color)?;
+            if link_level {
+                format_hyperlink_end(url, f)?;
             }
-            write!(f, "{}", color.render_reset())?;
 
             if !is_annotation_empty(annotation) {
                 if in_source {
-                    write!(f, "{}", color.render())?;
+                    f.set_style(color)?;
                     f.write_str(": ")?;
-                    self.format_label(&annotation.label, stylesheet, f)?;
-                    write!(f, "{}", color.render_reset())?;
+                    self.format_label(&annotation.label, renderer, f)?;
+                    f.reset_style(color)?;
                 } else {
                     f.write_str(": ")?;
-                    self.format_label(&annotation.label, stylesheet, f)?;
+                    self.format_label(&annotation.label, renderer, f)?;
                 }
             }
             Ok(())
@@ -228,8 +406,8 @@ impl<'a> DisplaySet<'a> {
         &self,
         line: &DisplayRawLine<'_>,
         lineno_width: usize,
-        stylesheet: &Stylesheet,
-        f: &mut fmt::Formatter<'_>,
+        renderer: &Renderer,
+        f: &mut dyn StyledWrite,
     ) -> fmt::Result {
         match line {
             DisplayRawLine::Origin {
@@ -238,37 +416,31 @@ impl<'a> DisplaySet<'a> {
                 header_type,
             } => {
                 let header_sigil = match header_type {
-                    DisplayHeaderType::Initial => "-->",
-                    DisplayHeaderType::Continuation => ":::",
+                    DisplayHeaderType::Initial => renderer.separators.file_start,
+                    DisplayHeaderType::Continuation | DisplayHeaderType::SameFile => {
+                        renderer.separators.secondary_file_start
+                    }
                 };
-                let lineno_color = stylesheet.line_no();
+                let lineno_color = self.line_no_style(renderer);
 
                 if let Some((col, row)) = pos {
                     format_repeat_char(' ', lineno_width, f)?;
-                    write!(
-                        f,
-                        "{}{}{}",
-                        lineno_color.render(),
-                        header_sigil,
-                        lineno_color.render_reset()
-                    )?;
+                    f.set_style(lineno_color)?;
+                    write!(f, "{header_sigil}")?;
+                    f.reset_style(lineno_color)?;
                     f.write_char(' ')?;
-                    path.fmt(f)?;
-                    f.write_char(':')?;
-                    col.fmt(f)?;
-                    f.write_char(':')?;
-                    row.fmt(f)
+                    if *header_type != DisplayHeaderType::SameFile {
+                        format_path(path, renderer, f)?;
+                        f.write_char(':')?;
+                    }
+                    write!(f, "{col}:{row}")
                 } else {
                     format_repeat_char(' ', lineno_width, f)?;
-                    write!(
-                        f,
-                        "{}{}{}",
-                        lineno_color.render(),
-                        header_sigil,
-                        lineno_color.render_reset()
-                    )?;
+                    f.set_style(lineno_color)?;
+                    write!(f, "{header_sigil}")?;
+                    f.reset_style(lineno_color)?;
                     f.write_char(' ')?;
-                    path.fmt(f)
+                    format_path(path, renderer, f)
                 }
             }
             DisplayRawLine::Annotation {
@@ -280,19 +452,24 @@ impl<'a> DisplaySet<'a> {
                     if *continuation {
                         format_repeat_char(' ', lineno_width + 3, f)?;
                     } else {
-                        let lineno_color = stylesheet.line_no();
+                        let lineno_color = if renderer.level_note_prefix {
+                            get_annotation_style(&annotation.annotation_type, &renderer.stylesheet)
+                        } else {
+                            &renderer.stylesheet.line_no
+                        };
                         format_repeat_char(' ', lineno_width, f)?;
                         f.write_char(' ')?;
-                        write!(
-                            f,
-                            "{}={}",
-                            lineno_color.render(),
-                            lineno_color.render_reset()
-                        )?;
+                        f.set_style(lineno_color)?;
+                        f.write_char(gutter_char(
+                            renderer.separators.note_separator,
+                            '=',
+                            renderer,
+                        ))?;
+                        f.reset_style(lineno_color)?;
                         f.write_char(' ')?;
                     }
                 }
-                self.format_annotation(annotation, *continuation, false, stylesheet, f)
+                self.format_annotation(annotation, *continuation, false, false, renderer, f)
             }
         }
     }
@@ -302,10 +479,10 @@ impl<'a> DisplaySet<'a> {
         &self,
         dl: &DisplayLine<'_>,
         lineno_width: usize,
+        local_lineno_width: usize,
         inline_marks_width: usize,
-        stylesheet: &Stylesheet,
-        anonymized_line_numbers: bool,
-        f: &mut fmt::Formatter<'_>,
+        renderer: &Renderer,
+        f: &mut dyn StyledWrite,
     ) -> fmt::Result {
         match dl {
             DisplayLine::Source {
@@ -314,39 +491,56 @@ impl<'a> DisplaySet<'a> {
                 line,
                 annotations,
             } => {
-                let lineno_color = stylesheet.line_no();
-                if anonymized_line_numbers && lineno.is_some() {
-                    write!(f, "{}", lineno_color.render())?;
-                    f.write_str(ANONYMIZED_LINE_NUM)?;
-                    f.write_str(" |")?;
-                    write!(f, "{}", lineno_color.render_reset())?;
+                let lineno_color = self.line_no_style(renderer);
+                if self.anonymized_line_numbers && lineno.is_some() {
+                    f.set_style(lineno_color)?;
+                    let placeholder = renderer.anonymized_line_num;
+                    let placeholder_width = display_width(placeholder, renderer);
+                    format_repeat_char(' ', lineno_width.saturating_sub(placeholder_width), f)?;
+                    f.write_str(placeholder)?;
+                    format_col_separator(renderer, f)?;
+                    f.reset_style(lineno_color)?;
                 } else {
-                    write!(f, "{}", lineno_color.render())?;
+                    f.set_style(lineno_color)?;
                     match lineno {
+                        Some(n) if local_lineno_width > 0 => write!(
+                            f,
+                            "{:>width$} {:>local_width$}",
+                            n,
+                            self.local_lineno(*n),
+                            width = lineno_width - local_lineno_width - 1,
+                            local_width = local_lineno_width,
+                        ),
                         Some(n) => write!(f, "{:>width$}", n, width = lineno_width),
                         None => format_repeat_char(' ', lineno_width, f),
                     }?;
-                    f.write_str(" |")?;
-                    write!(f, "{}", lineno_color.render_reset())?;
+                    format_col_separator(renderer, f)?;
+                    f.reset_style(lineno_color)?;
                 }
 
-                if let DisplaySourceLine::Content { text, .. } = line {
+                if let DisplaySourceLine::Content { text, range, .. } = line {
                     if !inline_marks.is_empty() || 0 < inline_marks_width {
                         f.write_char(' ')?;
-                        self.format_inline_marks(inline_marks, inline_marks_width, stylesheet, f)?;
+                        self.format_inline_marks(inline_marks, inline_marks_width, renderer, f)?;
                     }
                     f.write_char(' ')?;
 
-                    let text = normalize_whitespace(text);
-                    let line_len = text.as_bytes().len();
+                    let base_styles = self.source_styles(text, *range, renderer);
+                    let text = normalize_whitespace(text, renderer.expanded_tab_width());
+                    let line_len = text.len();
                     let mut left = self.margin.left(line_len);
                     let right = self.margin.right(line_len);
 
+                    let cut_left_width =
+                        CharWidths::new(false, renderer).str_width(renderer.cut_left);
+                    let cut_right_width =
+                        CharWidths::new(false, renderer).str_width(renderer.cut_right);
                     if self.margin.was_cut_left() {
-                        "...".fmt(f)?;
-                        left += 3;
+                        f.write_str(renderer.cut_left)?;
+                        left += cut_left_width;
                     }
                     // On long lines, we strip the source line, accounting for unicode.
+                    let mut widths = CharWidths::new(self.source_has_ansi, renderer);
                     let mut taken = 0;
                     let code: String = text
                         .chars()
@@ -356,7 +550,7 @@ impl<'a> DisplaySet<'a> {
                             // FIXME: `unicode_width` sometimes disagrees with terminals on how wide a `char`
                             // is. For now, just accept that sometimes the code line will be longer than
                             // desired.
-                            let next = unicode_width::UnicodeWidthChar::width(*ch).unwrap_or(1);
+                            let next = widths.width(*ch).unwrap_or(1);
                             if taken + next > right - left {
                                 return false;
                             }
@@ -365,103 +559,540 @@ impl<'a> DisplaySet<'a> {
                         })
                         .collect();
 
-                    if self.margin.was_cut_right(line_len) {
-                        code[..taken.saturating_sub(3)].fmt(f)?;
-                        "...".fmt(f)?;
-                    } else {
-                        code.fmt(f)?;
-                    }
-
                     let mut left: usize = text
                         .chars()
                         .take(left)
-                        .map(|ch| unicode_width::UnicodeWidthChar::width(ch).unwrap_or(1))
+                        .map({
+                            let mut widths = CharWidths::new(self.source_has_ansi, renderer);
+                            move |ch| widths.width(ch).unwrap_or(1)
+                        })
                         .sum();
 
+                    // Keep terminals from reordering right-to-left text away
+                    // from the underlines
+                    let force_ltr = !renderer.assume_ltr && code.chars().any(is_rtl);
+                    if force_ltr {
+                        f.write_char(LEFT_TO_RIGHT_OVERRIDE)?;
+                    }
+                    if self.margin.was_cut_right(line_len) {
+                        // Make room for the marker, cutting between chars. As
+                        // escape sequences take up no columns, they are only
+                        // ever cut off as a whole.
+                        let limit = taken.saturating_sub(cut_right_width);
+                        let mut widths = CharWidths::new(self.source_has_ansi, renderer);
+                        let mut width = 0;
+                        let end = code
+                            .char_indices()
+                            .find_map(|(idx, ch)| {
+                                let next = widths.width(ch).unwrap_or(1);
+                                if width + next > limit {
+                                    return Some(idx);
+                                }
+                                width += next;
+                                None
+                            })
+                            .unwrap_or(code.len());
+                        let code = &code[..end];
+                        self.format_code(code, left, annotations, &base_styles, renderer, f)?;
+                        f.write_str(renderer.cut_right)?;
+                    } else {
+                        self.format_code(&code, left, annotations, &base_styles, renderer, f)?;
+                    }
+                    if force_ltr {
+                        f.write_char(POP_DIRECTIONAL_FORMATTING)?;
+                    }
+                    if self.source_has_ansi && code.contains('\x1b') {
+                        // Don't let the styling of the source leak into what follows
+                        f.write_escape("\x1b[0m")?;
+                    }
+
                     if self.margin.was_cut_left() {
-                        left = left.saturating_sub(3);
+                        left = left.saturating_sub(cut_left_width);
+                    }
+
+                    let stacked = self.stacked_annotations(annotations, renderer);
+                    if !stacked.is_empty() {
+                        self.format_stacked_labels(
+                            &stacked,
+                            left,
+                            inline_marks,
+                            lineno_width,
+                            inline_marks_width,
+                            renderer,
+                            f,
+                        )?;
                     }
 
-                    for annotation in annotations {
+                    let mut prev: Option<&DisplaySourceAnnotation<'_>> = None;
+                    for annotation in annotations.iter().filter(|a| {
+                        !a.simplified && !stacked.iter().any(|stacked| ptr::eq(*stacked, *a))
+                    }) {
+                        if self.is_highlighted_inline(annotation, renderer)
+                            && is_annotation_empty(&annotation.annotation)
+                            && annotation.multi_line_label.is_none()
+                        {
+                            // The highlighted source is all there is to show
+                            continue;
+                        }
+                        let shares_underline =
+                            prev.map_or(false, |prev| shares_underline(prev, annotation));
+                        prev = Some(annotation);
                         // Each annotation should be on its own line
-                        f.write_char('\n')?;
-                        // Add the line number and the line number delimiter
-                        write!(f, "{}", stylesheet.line_no.render())?;
-                        format_repeat_char(' ', lineno_width, f)?;
-                        f.write_str(" |")?;
-                        write!(f, "{}", stylesheet.line_no.render_reset())?;
+                        self.format_annotation_line_start(
+                            inline_marks,
+                            lineno_width,
+                            inline_marks_width,
+                            renderer,
+                            f,
+                        )?;
+                        self.format_source_annotation(
+                            annotation,
+                            shares_underline,
+                            left,
+                            inline_marks,
+                            lineno_width,
+                            inline_marks_width,
+                            renderer,
+                            f,
+                        )?;
 
-                        if !inline_marks.is_empty() || 0 < inline_marks_width {
-                            f.write_char(' ')?;
-                            self.format_inline_marks(
+                        let Some(label) = &annotation.multi_line_label else {
+                            continue;
+                        };
+                        let rows = label.aligned_rows(renderer);
+                        if !self.multi_line_label_fits(&rows, annotation, left, renderer) {
+                            continue;
+                        }
+                        let color = source_annotation_style(
+                            &annotation.annotation_type,
+                            annotation.dimmed,
+                            renderer,
+                        );
+                        for row in rows {
+                            self.format_annotation_line_start(
                                 inline_marks,
+                                lineno_width,
                                 inline_marks_width,
-                                stylesheet,
+                                renderer,
+                                f,
+                            )?;
+                            format_repeat_char(
+                                ' ',
+                                annotation.range.0.saturating_sub(left) + 1,
                                 f,
                             )?;
+                            f.set_style(color)?;
+                            f.write_str(&row)?;
+                            f.reset_style(color)?;
                         }
-                        self.format_source_annotation(annotation, left, stylesheet, f)?;
+                    }
+
+                    let simplified = annotations
+                        .iter()
+                        .filter(|a| a.simplified && !self.is_highlighted_inline(a, renderer))
+                        .collect::<Vec<_>>();
+                    if !simplified.is_empty() {
+                        self.format_annotation_line_start(
+                            inline_marks,
+                            lineno_width,
+                            inline_marks_width,
+                            renderer,
+                            f,
+                        )?;
+                        format_underline_row(&simplified, left, renderer, f)?;
                     }
                 } else if !inline_marks.is_empty() {
                     f.write_char(' ')?;
-                    self.format_inline_marks(inline_marks, inline_marks_width, stylesheet, f)?;
+                    self.format_inline_marks(inline_marks, inline_marks_width, renderer, f)?;
                 }
                 Ok(())
             }
-            DisplayLine::Fold { inline_marks } => {
+            DisplayLine::Fold {
+                inline_marks,
+                folded_lines,
+            } => {
                 f.write_str("...")?;
                 if !inline_marks.is_empty() || 0 < inline_marks_width {
                     format_repeat_char(' ', lineno_width, f)?;
-                    self.format_inline_marks(inline_marks, inline_marks_width, stylesheet, f)?;
+                    self.format_inline_marks(inline_marks, inline_marks_width, renderer, f)?;
+                }
+                if renderer.count_folded_lines && !self.anonymized_line_numbers {
+                    let plural = if *folded_lines == 1 { "" } else { "s" };
+                    write!(f, " ({folded_lines} line{plural})")?;
                 }
                 Ok(())
             }
-            DisplayLine::Raw(line) => self.format_raw_line(line, lineno_width, stylesheet, f),
+            DisplayLine::Raw(line) => self.format_raw_line(line, lineno_width, renderer, f),
+        }
+    }
+
+    /// Start a new line below a source line, up to where an annotation is drawn
+    fn format_annotation_line_start(
+        &self,
+        inline_marks: &[DisplayMark],
+        lineno_width: usize,
+        inline_marks_width: usize,
+        renderer: &Renderer,
+        f: &mut dyn StyledWrite,
+    ) -> fmt::Result {
+        f.write_str(renderer.line_ending.as_str())?;
+        format_repeat_char(' ', renderer.indent, f)?;
+        // Add the line number and the line number delimiter
+        f.set_style(self.line_no_style(renderer))?;
+        format_repeat_char(' ', lineno_width, f)?;
+        format_col_separator(renderer, f)?;
+        f.reset_style(self.line_no_style(renderer))?;
+
+        if !inline_marks.is_empty() || 0 < inline_marks_width {
+            f.write_char(' ')?;
+            self.format_inline_marks(inline_marks, inline_marks_width, renderer, f)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the aligned `rows` of a [`MultiLineLabel`] fit below
+    /// `annotation`
+    fn multi_line_label_fits(
+        &self,
+        rows: &[String],
+        annotation: &DisplaySourceAnnotation<'_>,
+        left: usize,
+        renderer: &Renderer,
+    ) -> bool {
+        let width = rows
+            .iter()
+            .map(|row| CharWidths::new(false, renderer).str_width(row))
+            .max()
+            .unwrap_or(0);
+        annotation.range.0.saturating_sub(left) + width <= self.margin.column_width()
+    }
+
+    /// The styles from [`Renderer::source_styler`] for the source line `text`
+    /// at `range` of the snippet, by display column
+    fn source_styles(
+        &self,
+        text: &str,
+        range: (usize, usize),
+        renderer: &Renderer,
+    ) -> Vec<((usize, usize), Style)> {
+        let Some(styler) = &renderer.source_styler else {
+            return vec![];
+        };
+        let range = self.source_offset + range.0..self.source_offset + range.1;
+        let str_width =
+            |text: &str| CharWidths::new(self.source_has_ansi, renderer).str_width(text);
+        styler
+            .style(text, range)
+            .into_iter()
+            .filter_map(|(span, style)| {
+                // Spans that aren't on char boundaries are skipped
+                let start = str_width(text.get(..span.start)?);
+                let end = str_width(text.get(..span.end)?);
+                Some(((start, end), style))
+            })
+            .collect()
+    }
+
+    /// Write the visible part of a source line, starting at display column `col`
+    ///
+    /// `base_styles` are used where no annotation highlights the source.
+    fn format_code(
+        &self,
+        code: &str,
+        mut col: usize,
+        annotations: &[DisplaySourceAnnotation<'_>],
+        base_styles: &[((usize, usize), Style)],
+        renderer: &Renderer,
+        f: &mut dyn StyledWrite,
+    ) -> fmt::Result {
+        let highlights = annotations
+            .iter()
+            .filter(|annotation| self.is_highlighted_inline(annotation, renderer))
+            .map(|annotation| {
+                (
+                    annotation.range,
+                    self.background_style(annotation, renderer),
+                )
+            })
+            .collect::<Vec<_>>();
+        if highlights.is_empty() && base_styles.is_empty() && !self.source_has_ansi {
+            return f.write_str(code);
+        }
+
+        let mut widths = CharWidths::new(self.source_has_ansi, renderer);
+        let mut current: Option<Style> = None;
+        let mut escape = String::new();
+        for ch in code.chars() {
+            let mut highlighted = highlights
+                .iter()
+                .filter(|(range, _)| range.0 <= col && col < range.1)
+                .peekable();
+            let underline = renderer.inline_underline && highlighted.peek().is_some();
+            let style = highlighted.find_map(|(_, style)| *style).or_else(|| {
+                base_styles
+                    .iter()
+                    .find(|(range, _)| range.0 <= col && col < range.1)
+                    .map(|(_, style)| *style)
+            });
+            if style != current {
+                if let Some(current) = current {
+                    f.reset_style(&current)?;
+                }
+                if let Some(style) = style {
+                    f.set_style(&style)?;
+                }
+                current = style;
+            }
+            if widths.is_escape(ch) {
+                // Escape sequences are passed on whole
+                escape.push(ch);
+                if widths.escape_ended() {
+                    f.write_escape(&escape)?;
+                    escape.clear();
+                }
+                continue;
+            }
+            f.write_char(ch)?;
+            if underline {
+                f.write_char(COMBINING_LOW_LINE)?;
+            }
+            col += widths.width(ch).unwrap_or(1);
+        }
+        if !escape.is_empty() {
+            f.write_escape(&escape)?;
+        }
+        if let Some(current) = current {
+            f.reset_style(&current)?;
+        }
+        Ok(())
+    }
+
+    /// Whether `annotation` is highlighted in the source line itself, rather
+    /// than with an underline below it
+    fn is_highlighted_inline(
+        &self,
+        annotation: &DisplaySourceAnnotation<'_>,
+        renderer: &Renderer,
+    ) -> bool {
+        annotation.annotation_part == DisplayAnnotationPart::Standalone
+            && (renderer.inline_underline || self.background_style(annotation, renderer).is_some())
+    }
+
+    /// The style to highlight the source of `annotation` with, when rendering
+    /// with [`HighlightStyle::Background`]
+    ///
+    /// Multiline annotations and annotations without a color fall back to
+    /// being underlined.
+    fn background_style(
+        &self,
+        annotation: &DisplaySourceAnnotation<'_>,
+        renderer: &Renderer,
+    ) -> Option<Style> {
+        if renderer.highlight_style != HighlightStyle::Background
+            || annotation.annotation_part != DisplayAnnotationPart::Standalone
+        {
+            return None;
         }
+        source_annotation_style(&annotation.annotation_type, annotation.dimmed, renderer)
+            .get_fg_color()
+            .map(|color| Style::new().bg_color(Some(color)))
     }
 
     fn format_inline_marks(
         &self,
         inline_marks: &[DisplayMark],
         inline_marks_width: usize,
-        stylesheet: &Stylesheet,
-        f: &mut fmt::Formatter<'_>,
+        renderer: &Renderer,
+        f: &mut dyn StyledWrite,
     ) -> fmt::Result {
         format_repeat_char(' ', inline_marks_width - inline_marks.len(), f)?;
         for mark in inline_marks {
-            let annotation_style = get_annotation_style(&mark.annotation_type, stylesheet);
-            write!(f, "{}", annotation_style.render())?;
+            let annotation_style =
+                source_annotation_style(&mark.annotation_type, mark.dimmed, renderer);
+            f.set_style(annotation_style)?;
             f.write_char(match mark.mark_type {
-                DisplayMarkType::AnnotationThrough => '|',
-                DisplayMarkType::AnnotationStart => '/',
+                DisplayMarkType::AnnotationThrough => renderer.underline_glyphs.multiline_vertical,
+                DisplayMarkType::AnnotationStart => renderer.multiline_whole_line,
             })?;
-            write!(f, "{}", annotation_style.render_reset())?;
+            f.reset_style(annotation_style)?;
+        }
+        Ok(())
+    }
+
+    /// The annotations of a source line to draw with their labels stacked,
+    /// when rendering with [`Renderer::label_leaders`] and more than one of
+    /// them has a label
+    fn stacked_annotations<'b, 'c>(
+        &self,
+        annotations: &'b [DisplaySourceAnnotation<'c>],
+        renderer: &Renderer,
+    ) -> Vec<&'b DisplaySourceAnnotation<'c>> {
+        if !renderer.label_leaders {
+            return vec![];
+        }
+        let stacked = annotations
+            .iter()
+            .filter(|annotation| {
+                annotation.annotation_part == DisplayAnnotationPart::Standalone
+                    && !annotation.simplified
+                    && !annotation.continues_before
+                    && !annotation.continues_after
+                    && annotation.multi_line_label.is_none()
+                    && !self.is_highlighted_inline(annotation, renderer)
+                    && !annotation
+                        .annotation
+                        .label
+                        .iter()
+                        .any(|fragment| fragment.content.contains('\n'))
+            })
+            .collect::<Vec<_>>();
+        let labeled = stacked
+            .iter()
+            .filter(|annotation| !is_annotation_empty(&annotation.annotation))
+            .count();
+        if labeled < 2 {
+            return vec![];
+        }
+        stacked
+    }
+
+    /// Draw the underlines of `stacked` annotations on one row, followed by a
+    /// row for each label, from the right-most underline to the left-most
+    #[allow(clippy::too_many_arguments)]
+    fn format_stacked_labels(
+        &self,
+        stacked: &[&DisplaySourceAnnotation<'_>],
+        left: usize,
+        inline_marks: &[DisplayMark],
+        lineno_width: usize,
+        inline_marks_width: usize,
+        renderer: &Renderer,
+        f: &mut dyn StyledWrite,
+    ) -> fmt::Result {
+        self.format_annotation_line_start(
+            inline_marks,
+            lineno_width,
+            inline_marks_width,
+            renderer,
+            f,
+        )?;
+        format_underline_row(stacked, left, renderer, f)?;
+
+        let start =
+            |annotation: &DisplaySourceAnnotation<'_>| annotation.range.0.saturating_sub(left);
+        // Labels are aligned one column right of the right-most underline
+        let label_col = stacked
+            .iter()
+            .map(|annotation| annotation.range.1.saturating_sub(left))
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let mut pending = stacked
+            .iter()
+            .filter(|annotation| !is_annotation_empty(&annotation.annotation))
+            .collect::<Vec<_>>();
+        pending.sort_by_key(|annotation| (start(annotation), annotation.range.1));
+        while let Some(annotation) = pending.pop() {
+            self.format_annotation_line_start(
+                inline_marks,
+                lineno_width,
+                inline_marks_width,
+                renderer,
+                f,
+            )?;
+            f.write_char(' ')?;
+            // Keep leading down to the labels still to come
+            let mut col = 0;
+            for other in pending
+                .iter()
+                .filter(|other| start(other) < start(annotation))
+            {
+                if start(other) < col {
+                    continue;
+                }
+                let color = source_annotation_style(&other.annotation_type, other.dimmed, renderer);
+                format_repeat_char(' ', start(other) - col, f)?;
+                f.set_style(color)?;
+                f.write_char(renderer.underline_glyphs.multiline_vertical)?;
+                f.reset_style(color)?;
+                col = start(other) + 1;
+            }
+            format_repeat_char(' ', start(annotation) - col, f)?;
+            f.set_style(&renderer.stylesheet.line_no)?;
+            format_repeat_char(
+                '.',
+                (label_col - 1).saturating_sub(start(annotation)).max(1),
+                f,
+            )?;
+            f.reset_style(&renderer.stylesheet.line_no)?;
+            f.write_char(' ')?;
+            let color =
+                source_annotation_style(&annotation.annotation_type, annotation.dimmed, renderer);
+            f.set_style(color)?;
+            self.format_annotation(
+                &annotation.annotation,
+                false,
+                true,
+                annotation.dimmed,
+                renderer,
+                f,
+            )?;
+            f.reset_style(color)?;
         }
         Ok(())
     }
 
+    /// The length of the leader connecting `annotation` to its label in the
+    /// side column, if its label fits there
+    fn side_label_leader(
+        &self,
+        annotation: &DisplaySourceAnnotation<'_>,
+        left: usize,
+        renderer: &Renderer,
+    ) -> Option<usize> {
+        let side_label_col = self.side_label_col?;
+        let type_len = annotation_type_len(&annotation.annotation.annotation_type, renderer);
+        let label_len = annotation
+            .annotation
+            .label
+            .iter()
+            .map(|fragment| CharWidths::new(false, renderer).str_width(&fragment.content))
+            .sum::<usize>()
+            + if type_len == 0 { 0 } else { type_len + 2 };
+        let label_col = side_label_col.saturating_sub(left) + 3;
+        (label_col + label_len <= self.margin.column_width())
+            .then(|| side_label_col + 1 - annotation.range.1)
+    }
+
+    /// Draw `annotation` below the source line, with a `|` leading to the
+    /// underline above instead of its own when it `shares_underline` with it
+    #[allow(clippy::too_many_arguments)]
     fn format_source_annotation(
         &self,
         annotation: &DisplaySourceAnnotation<'_>,
+        shares_underline: bool,
         left: usize,
-        stylesheet: &Stylesheet,
-        f: &mut fmt::Formatter<'_>,
+        inline_marks: &[DisplayMark],
+        lineno_width: usize,
+        inline_marks_width: usize,
+        renderer: &Renderer,
+        f: &mut dyn StyledWrite,
     ) -> fmt::Result {
         let indent_char = match annotation.annotation_part {
             DisplayAnnotationPart::Standalone => ' ',
             DisplayAnnotationPart::LabelContinuation => ' ',
-            DisplayAnnotationPart::MultilineStart => '_',
-            DisplayAnnotationPart::MultilineEnd => '_',
+            DisplayAnnotationPart::MultilineStart | DisplayAnnotationPart::MultilineEnd => {
+                renderer.underline_glyphs.multiline_horizontal
+            }
         };
-        let mark = match annotation.annotation_type {
-            DisplayAnnotationType::Error => '^',
-            DisplayAnnotationType::Warning => '-',
-            DisplayAnnotationType::Info => '-',
-            DisplayAnnotationType::Note => '-',
-            DisplayAnnotationType::Help => '-',
-            DisplayAnnotationType::None => ' ',
+        let glyphs = &renderer.underline_glyphs;
+        let mark = if shares_underline || self.is_highlighted_inline(annotation, renderer) {
+            ' '
+        } else {
+            underline_mark(&annotation.annotation_type, renderer)
         };
-        let color = get_annotation_style(&annotation.annotation_type, stylesheet);
+        let color =
+            source_annotation_style(&annotation.annotation_type, annotation.dimmed, renderer);
         let range = (
             annotation.range.0.saturating_sub(left),
             annotation.range.1.saturating_sub(left),
@@ -470,32 +1101,127 @@ impl<'a> DisplaySet<'a> {
             DisplayAnnotationPart::LabelContinuation => range.1,
             _ => range.0,
         };
-        write!(f, "{}", color.render())?;
-        format_repeat_char(indent_char, indent_length + 1, f)?;
-        format_repeat_char(mark, range.1 - indent_length, f)?;
-        write!(f, "{}", color.render_reset())?;
-
-        if !is_annotation_empty(&annotation.annotation) {
+        let clipped_mark = if shares_underline { ' ' } else { CLIPPED_MARK };
+        f.set_style(color)?;
+        if annotation.continues_before {
+            format_repeat_char(indent_char, indent_length, f)?;
+            f.write_char(clipped_mark)?;
+        } else {
+            format_repeat_char(indent_char, indent_length + 1, f)?;
+        }
+        let mut marks = range.1 - indent_length;
+        if shares_underline && marks > 0 {
+            // Connect the label to the underline above it
+            f.write_char(glyphs.multiline_vertical)?;
+            marks -= 1;
+        }
+        format_repeat_char(mark, marks, f)?;
+        if annotation.continues_after {
+            f.write_char(clipped_mark)?;
+        }
+        f.reset_style(color)?;
+
+        // A multi-line label that doesn't fit is shown after the label instead
+        let plain_text = annotation
+            .multi_line_label
+            .as_ref()
+            .filter(|label| {
+                !self.multi_line_label_fits(
+                    &label.aligned_rows(renderer),
+                    annotation,
+                    left,
+                    renderer,
+                )
+            })
+            .map(MultiLineLabel::to_plain_text);
+        if !is_annotation_empty(&annotation.annotation) || plain_text.is_some() {
             f.write_char(' ')?;
-            write!(f, "{}", color.render())?;
-            self.format_annotation(
-                &annotation.annotation,
-                annotation.annotation_part == DisplayAnnotationPart::LabelContinuation,
-                true,
-                stylesheet,
-                f,
-            )?;
-            write!(f, "{}", color.render_reset())?;
+            let mut label_col = range.1 + usize::from(annotation.continues_after) + 1;
+            if let Some(leader) = self.side_label_leader(annotation, left, renderer) {
+                f.set_style(&renderer.stylesheet.line_no)?;
+                format_repeat_char('.', leader, f)?;
+                f.reset_style(&renderer.stylesheet.line_no)?;
+                f.write_char(' ')?;
+                label_col += leader + 1;
+            }
+            let label = |f: &mut dyn StyledWrite| {
+                f.set_style(color)?;
+                self.format_annotation(
+                    &annotation.annotation,
+                    annotation.annotation_part == DisplayAnnotationPart::LabelContinuation,
+                    true,
+                    annotation.dimmed,
+                    renderer,
+                    f,
+                )?;
+                if let Some(plain_text) = &plain_text {
+                    if !is_annotation_empty(&annotation.annotation) {
+                        f.write_str(", ")?;
+                    }
+                    f.write_str(plain_text)?;
+                }
+                f.reset_style(color)
+            };
+            let has_line_breaks = annotation
+                .annotation
+                .label
+                .iter()
+                .any(|fragment| fragment.content.contains('\n'));
+            if !renderer.wrap_labels && !has_line_breaks {
+                return label(f);
+            }
+
+            // Continue the label on the next line, aligned under its start
+            let new_line = |f: &mut dyn StyledWrite| {
+                f.reset_style(color)?;
+                self.format_annotation_line_start(
+                    inline_marks,
+                    lineno_width,
+                    inline_marks_width,
+                    renderer,
+                    f,
+                )?;
+                format_repeat_char(' ', label_col + 1, f)?;
+                f.set_style(color)
+            };
+            let mut recorded = Recording::default();
+            label(&mut recorded)?;
+            let available = self.margin.column_width().saturating_sub(label_col);
+            for (idx, line) in recorded.lines().iter().enumerate() {
+                if 0 < idx {
+                    new_line(f)?;
+                }
+                let mut line_width = 0;
+                for word in line {
+                    let word_width = word.width(renderer);
+                    if renderer.wrap_labels
+                        && 0 < line_width
+                        && available < line_width + 1 + word_width
+                    {
+                        new_line(f)?;
+                        line_width = 0;
+                    } else if 0 < line_width {
+                        f.write_char(' ')?;
+                        line_width += 1;
+                    }
+                    word.replay(f)?;
+                    line_width += word_width;
+                }
+            }
         }
         Ok(())
     }
 }
 
+/// A `(line, column)` in the source, as shown in an origin header
+pub(crate) type Position = (usize, usize);
+
 /// Inline annotation which can be used in either Raw or Source line.
 #[derive(Debug, PartialEq)]
 pub(crate) struct Annotation<'a> {
     pub(crate) annotation_type: DisplayAnnotationType,
     pub(crate) id: Option<&'a str>,
+    pub(crate) id_url: Option<&'a str>,
     pub(crate) label: Vec<DisplayTextFragment<'a>>,
 }
 
@@ -511,7 +1237,11 @@ pub(crate) enum DisplayLine<'a> {
     },
 
     /// A line indicating a folded part of the slice.
-    Fold { inline_marks: Vec<DisplayMark> },
+    Fold {
+        inline_marks: Vec<DisplayMark>,
+        /// The number of source lines left out
+        folded_lines: usize,
+    },
 
     /// A line which is displayed outside of slices.
     Raw(DisplayRawLine<'a>),
@@ -536,6 +1266,17 @@ pub(crate) struct DisplaySourceAnnotation<'a> {
     pub(crate) range: (usize, usize),
     pub(crate) annotation_type: DisplayAnnotationType,
     pub(crate) annotation_part: DisplayAnnotationPart,
+    /// Whether the annotation is out of [`Renderer::focus`]
+    pub(crate) dimmed: bool,
+    pub(crate) multi_line_label: Option<MultiLineLabel<'a>>,
+    /// Whether to mark the annotation as continuing into folded lines before
+    /// it, see [`Snippet::clip_to_window`][crate::Snippet::clip_to_window]
+    pub(crate) continues_before: bool,
+    /// Whether to mark the annotation as continuing into folded lines after it
+    pub(crate) continues_after: bool,
+    /// Whether the label was moved into the `N annotations simplified` note,
+    /// see [`Renderer::max_annotations_per_line`]
+    pub(crate) simplified: bool,
 }
 
 /// Raw line - a line which does not have the `lineno` part and is not considered
@@ -545,7 +1286,7 @@ pub(crate) enum DisplayRawLine<'a> {
     /// A line which provides information about the location of the given
     /// slice in the project structure.
     Origin {
-        path: &'a str,
+        path: Cow<'a, str>,
         pos: Option<(usize, usize)>,
         header_type: DisplayHeaderType,
     },
@@ -568,7 +1309,7 @@ pub(crate) enum DisplayRawLine<'a> {
 /// An inline text fragment which any label is composed of.
 #[derive(Debug, PartialEq)]
 pub(crate) struct DisplayTextFragment<'a> {
-    pub(crate) content: &'a str,
+    pub(crate) content: Cow<'a, str>,
     pub(crate) style: DisplayTextStyle,
 }
 
@@ -599,6 +1340,8 @@ pub(crate) enum DisplayAnnotationPart {
 pub(crate) struct DisplayMark {
     pub(crate) mark_type: DisplayMarkType,
     pub(crate) annotation_type: DisplayAnnotationType,
+    /// Whether the annotation is out of [`Renderer::focus`]
+    pub(crate) dimmed: bool,
 }
 
 /// A type of the `DisplayMark`.
@@ -625,6 +1368,7 @@ pub(crate) enum DisplayAnnotationType {
     Info,
     Note,
     Help,
+    Custom(&'static str, Style),
 }
 
 impl From<snippet::Level> for DisplayAnnotationType {
@@ -635,6 +1379,10 @@ impl From<snippet::Level> for DisplayAnnotationType {
             snippet::Level::Info => DisplayAnnotationType::Info,
             snippet::Level::Note => DisplayAnnotationType::Note,
             snippet::Level::Help => DisplayAnnotationType::Help,
+            snippet::Level::None => DisplayAnnotationType::None,
+            snippet::Level::Custom(custom) => {
+                DisplayAnnotationType::Custom(custom.name, custom.style)
+            }
         }
     }
 }
@@ -649,6 +1397,10 @@ pub(crate) enum DisplayHeaderType {
 
     /// Continuation marks all headers of following slices in the snippet.
     Continuation,
+
+    /// A continuation in the same file as the previous slice, when rendering
+    /// with [`Renderer::group_by_file`]; only the position is shown.
+    SameFile,
 }
 
 struct CursorLines<'a>(&'a str);
@@ -697,36 +1449,54 @@ impl<'a> Iterator for CursorLines<'a> {
     }
 }
 
-fn format_message(
-    message: snippet::Message<'_>,
-    term_width: usize,
-    anonymized_line_numbers: bool,
-    primary: bool,
-) -> Vec<DisplaySet<'_>> {
+/// Lay out `message`, or a footer of a message whose first snippet is from
+/// `primary_origin`
+///
+/// `prev_origin` is the origin of the snippet laid out last, if any, and is
+/// updated as snippets are laid out.
+fn format_message<'a>(
+    message: snippet::Message<'a>,
+    renderer: &Renderer,
+    primary_origin: Option<&Option<Cow<'a, str>>>,
+    prev_origin: &mut Option<Cow<'a, str>>,
+) -> Vec<DisplaySet<'a>> {
     let snippet::Message {
         level,
         id,
+        id_url,
         title,
         footer,
         snippets,
     } = message;
 
+    let primary = primary_origin.is_none();
+    let primary_origin = match primary_origin {
+        Some(origin) => origin.clone(),
+        None => snippets.first().and_then(|snippet| snippet.origin.clone()),
+    };
     let mut sets = vec![];
+    let tab_width = renderer.expanded_tab_width();
     let body = if !snippets.is_empty() || primary {
-        vec![format_title(level, id, title)]
+        vec![format_title(level, id, id_url, title, tab_width)]
     } else {
-        format_footer(level, id, title)
+        format_footer(level, id, id_url, title, tab_width)
     };
 
     for (idx, snippet) in snippets.into_iter().enumerate() {
-        let snippet = fold_prefix_suffix(snippet);
-        sets.push(format_snippet(
-            snippet,
-            idx == 0,
-            !footer.is_empty(),
-            term_width,
-            anonymized_line_numbers,
-        ));
+        let same_file =
+            renderer.group_by_file && snippet.origin.is_some() && snippet.origin == *prev_origin;
+        *prev_origin = snippet.origin.clone();
+        let secondary_file = snippet.origin != primary_origin;
+        let excerpt_line_start = snippet.line_start;
+        let (snippet, source_offset) = fold_prefix_suffix(snippet);
+        let mut set = format_snippet(snippet, idx == 0, !footer.is_empty(), renderer);
+        set.excerpt_line_start = excerpt_line_start;
+        set.source_offset = source_offset;
+        set.secondary_file = secondary_file;
+        if same_file {
+            shorten_header(&mut set);
+        }
+        sets.push(set);
     }
 
     if let Some(first) = sets.first_mut() {
@@ -737,27 +1507,55 @@ fn format_message(
         sets.push(DisplaySet {
             display_lines: body,
             margin: Margin::new(0, 0, 0, 0, DEFAULT_TERM_WIDTH, 0),
+            anonymized_line_numbers: renderer.anonymized_line_numbers,
+            excerpt_line_start: 1,
+            source_has_ansi: false,
+            source_offset: 0,
+            side_label_col: None,
+            secondary_file: false,
         });
     }
 
     for annotation in footer {
         sets.extend(format_message(
             annotation,
-            term_width,
-            anonymized_line_numbers,
-            false,
+            renderer,
+            Some(&primary_origin),
+            prev_origin,
         ));
     }
 
     sets
 }
 
-fn format_title<'a>(level: crate::Level, id: Option<&'a str>, label: &'a str) -> DisplayLine<'a> {
+/// Show only the position in the origin header of `set`, dropping the header
+/// if there is no position to show
+fn shorten_header(set: &mut DisplaySet<'_>) {
+    if let Some(DisplayLine::Raw(DisplayRawLine::Origin {
+        pos, header_type, ..
+    })) = set.display_lines.first_mut()
+    {
+        if pos.is_some() {
+            *header_type = DisplayHeaderType::SameFile;
+        } else {
+            set.display_lines.remove(0);
+        }
+    }
+}
+
+fn format_title<'a>(
+    level: crate::Level,
+    id: Option<&'a str>,
+    id_url: Option<&'a str>,
+    label: &'a str,
+    tab_width: usize,
+) -> DisplayLine<'a> {
     DisplayLine::Raw(DisplayRawLine::Annotation {
         annotation: Annotation {
             annotation_type: DisplayAnnotationType::from(level),
             id,
-            label: format_label(Some(label), Some(DisplayTextStyle::Emphasis)),
+            id_url,
+            label: format_label(Some(label), Some(DisplayTextStyle::Emphasis), tab_width),
         },
         source_aligned: false,
         continuation: false,
@@ -767,7 +1565,9 @@ fn format_title<'a>(level: crate::Level, id: Option<&'a str>, label: &'a str) ->
 fn format_footer<'a>(
     level: crate::Level,
     id: Option<&'a str>,
+    id_url: Option<&'a str>,
     label: &'a str,
+    tab_width: usize,
 ) -> Vec<DisplayLine<'a>> {
     let mut result = vec![];
     for (i, line) in label.lines().enumerate() {
@@ -775,7 +1575,8 @@ fn format_footer<'a>(
             annotation: Annotation {
                 annotation_type: DisplayAnnotationType::from(level),
                 id,
-                label: format_label(Some(line), None),
+                id_url,
+                label: format_label(Some(line), None, tab_width),
             },
             source_aligned: true,
             continuation: i != 0,
@@ -787,35 +1588,104 @@ fn format_footer<'a>(
 fn format_label(
     label: Option<&str>,
     style: Option<DisplayTextStyle>,
+    tab_width: usize,
 ) -> Vec<DisplayTextFragment<'_>> {
     let mut result = vec![];
     if let Some(label) = label {
         let element_style = style.unwrap_or(DisplayTextStyle::Regular);
         result.push(DisplayTextFragment {
-            content: label,
+            content: normalize_title(label, tab_width),
             style: element_style,
         });
     }
     result
 }
 
-fn format_snippet(
-    snippet: snippet::Snippet<'_>,
-    is_first: bool,
-    has_footer: bool,
-    term_width: usize,
-    anonymized_line_numbers: bool,
-) -> DisplaySet<'_> {
-    let main_range = snippet.annotations.first().map(|x| x.range.start);
-    let origin = snippet.origin;
+fn format_annotation_label<'a>(
+    label: Option<Cow<'a, str>>,
+    level: crate::Level,
+    renderer: &Renderer,
+) -> Vec<DisplayTextFragment<'a>> {
+    let mut result: Vec<_> = label
+        .into_iter()
+        .map(|label| DisplayTextFragment {
+            content: label,
+            style: DisplayTextStyle::Regular,
+        })
+        .collect();
+    if renderer.normalize_labels {
+        for fragment in &mut result {
+            fragment.content = Cow::Owned(normalize_whitespace(
+                &fragment.content,
+                renderer.expanded_tab_width(),
+            ));
+        }
+    }
+    if let (Some(tag), false) = (kind_tag(level, renderer), result.is_empty()) {
+        result.push(DisplayTextFragment {
+            content: Cow::Owned(format!(" {tag}")),
+            style: DisplayTextStyle::Regular,
+        });
+    }
+    if let (Some(icon), false) = (label_icon(level, renderer), result.is_empty()) {
+        result.insert(
+            0,
+            DisplayTextFragment {
+                content: Cow::Owned(format!("{icon} ")),
+                style: DisplayTextStyle::Regular,
+            },
+        );
+    }
+    result
+}
+
+/// The tag to append to labels of `level` with, when rendering with
+/// [`Renderer::annotate_kinds`]
+fn kind_tag(level: crate::Level, renderer: &Renderer) -> Option<&'static str> {
+    if !renderer.annotate_kinds {
+        return None;
+    }
+    match level {
+        crate::Level::Error => Some("[primary]"),
+        crate::Level::Warning
+        | crate::Level::Info
+        | crate::Level::Note
+        | crate::Level::Help
+        | crate::Level::Custom(..) => Some("[context]"),
+        crate::Level::None => None,
+    }
+}
+
+/// The icon to prefix labels of `level` with, when rendering with
+/// [`Renderer::label_icons`]
+fn label_icon(level: crate::Level, renderer: &Renderer) -> Option<&'static str> {
+    if !renderer.label_icons {
+        return None;
+    }
+    match level {
+        crate::Level::Error => Some("[x]"),
+        crate::Level::Warning => Some("[!]"),
+        crate::Level::Info => Some("[i]"),
+        crate::Level::Note => Some("[*]"),
+        crate::Level::Help => Some("[?]"),
+        crate::Level::None | crate::Level::Custom(..) => None,
+    }
+}
+
+fn format_snippet<'a>(
+    mut snippet: snippet::Snippet<'a>,
+    is_first: bool,
+    has_footer: bool,
+    renderer: &Renderer,
+) -> DisplaySet<'a> {
+    let main_range = snippet
+        .annotations
+        .iter()
+        .find(|x| !x.layout_passive)
+        .map(|x| x.range.start);
+    let origin = snippet.origin.take().filter(|_| renderer.show_origin);
     let need_empty_header = origin.is_some() || is_first;
-    let mut body = format_body(
-        snippet,
-        need_empty_header,
-        has_footer,
-        term_width,
-        anonymized_line_numbers,
-    );
+    let mut body = format_body(snippet, need_empty_header, has_footer, renderer);
     let header = format_header(origin, main_range, &body.display_lines, is_first);
 
     if let Some(header) = header {
@@ -825,14 +1695,8 @@ fn format_snippet(
     body
 }
 
-#[inline]
-// TODO: option_zip
-fn zip_opt<A, B>(a: Option<A>, b: Option<B>) -> Option<(A, B)> {
-    a.and_then(|a| b.map(|b| (a, b)))
-}
-
 fn format_header<'a>(
-    origin: Option<&'a str>,
+    origin: Option<Cow<'a, str>>,
     main_range: Option<usize>,
     body: &[DisplayLine<'_>],
     is_first: bool,
@@ -843,7 +1707,8 @@ fn format_header<'a>(
         DisplayHeaderType::Continuation
     };
 
-    if let Some((main_range, path)) = zip_opt(main_range, origin) {
+    let path = origin?;
+    let pos = main_range.map(|main_range| {
         let mut col = 1;
         let mut line_offset = 1;
 
@@ -859,7 +1724,13 @@ fn format_header<'a>(
                 ..
             } = item
             {
-                if main_range >= range.0 && main_range <= range.1 + *end_line as usize {
+                // The start of the next line belongs to the next line, unless
+                // we're pointing at the end of the file
+                let line_end = range.1 + *end_line as usize;
+                if main_range >= range.0
+                    && (main_range < line_end
+                        || (main_range == line_end && *end_line == EndLine::Eof))
+                {
                     let char_column = text[0..(main_range - range.0).min(text.len())]
                         .chars()
                         .count();
@@ -870,29 +1741,27 @@ fn format_header<'a>(
             }
         }
 
-        return Some(DisplayLine::Raw(DisplayRawLine::Origin {
-            path,
-            pos: Some((line_offset, col)),
-            header_type: display_header,
-        }));
-    }
-
-    if let Some(path) = origin {
-        return Some(DisplayLine::Raw(DisplayRawLine::Origin {
-            path,
-            pos: None,
-            header_type: display_header,
-        }));
-    }
+        (line_offset, col)
+    });
 
-    None
+    Some(DisplayLine::Raw(DisplayRawLine::Origin {
+        path,
+        pos,
+        header_type: display_header,
+    }))
 }
 
-fn fold_prefix_suffix(mut snippet: snippet::Snippet<'_>) -> snippet::Snippet<'_> {
+/// Drop the lines before the first and after the last annotation of a
+/// folded `snippet`, returning it with the byte offset of its new `source` in
+/// the original one
+fn fold_prefix_suffix(mut snippet: snippet::Snippet<'_>) -> (snippet::Snippet<'_>, usize) {
+    clamp_spans(&mut snippet);
     if !snippet.fold {
-        return snippet;
+        return (snippet, 0);
     }
 
+    let mut source_offset = 0;
+
     let ann_start = snippet
         .annotations
         .iter()
@@ -906,6 +1775,7 @@ fn fold_prefix_suffix(mut snippet: snippet::Snippet<'_>) -> snippet::Snippet<'_>
         snippet.line_start += line_offset;
 
         snippet.source = &snippet.source[new_start..];
+        source_offset = new_start;
 
         for ann in &mut snippet.annotations {
             let range_start = ann.range.start - new_start;
@@ -920,18 +1790,34 @@ fn fold_prefix_suffix(mut snippet: snippet::Snippet<'_>) -> snippet::Snippet<'_>
         .map(|ann| ann.range.end)
         .max()
         .unwrap_or(snippet.source.len());
-    if let Some(end_offset) = snippet.source[ann_end..].find('\n') {
-        let new_end = ann_end + end_offset;
+    // Keep the line of the last annotation and `trailing_context` more
+    if let Some(new_end) = snippet.source[ann_end..]
+        .match_indices('\n')
+        .map(|(end_offset, _)| ann_end + end_offset)
+        .nth(snippet.trailing_context)
+    {
         snippet.source = &snippet.source[..new_end];
     }
 
-    snippet
+    (snippet, source_offset)
 }
 
-fn fold_body(body: Vec<DisplayLine<'_>>) -> Vec<DisplayLine<'_>> {
-    const INNER_CONTEXT: usize = 1;
-    const INNER_UNFOLD_SIZE: usize = INNER_CONTEXT * 2 + 1;
+/// The number of unannotated lines kept around a fold
+const INNER_CONTEXT: usize = 1;
+
+/// The most unannotated lines that are shown rather than folded, see
+/// [`Renderer::max_unfolded_lines`]
+fn unfold_size(renderer: &Renderer) -> usize {
+    // Folding fewer lines than the context around the fold would hide nothing
+    renderer.max_unfolded_lines.max(INNER_CONTEXT * 2)
+}
 
+fn fold_body<'a>(
+    body: Vec<DisplayLine<'a>>,
+    trailing_context: usize,
+    renderer: &Renderer,
+) -> Vec<DisplayLine<'a>> {
+    let unfold_size = unfold_size(renderer);
     let mut lines = vec![];
     let mut unhighlighed_lines = vec![];
     for line in body {
@@ -956,7 +1842,7 @@ fn fold_body(body: Vec<DisplayLine<'_>>) -> Vec<DisplayLine<'_>> {
                     }
                     match unhighlighed_lines.len() {
                         0 => {}
-                        n if n <= INNER_UNFOLD_SIZE => {
+                        n if n <= unfold_size => {
                             // Rather than render `...`, don't fold
                             lines.append(&mut unhighlighed_lines);
                         }
@@ -979,11 +1865,14 @@ fn fold_body(body: Vec<DisplayLine<'_>>) -> Vec<DisplayLine<'_>> {
                                     }
                                 })
                                 .unwrap_or_default();
+                            let folded_lines = unhighlighed_lines
+                                .drain(..unhighlighed_lines.len().saturating_sub(INNER_CONTEXT))
+                                .filter(|line| matches!(line, DisplayLine::Source { .. }))
+                                .count();
                             lines.push(DisplayLine::Fold {
                                 inline_marks: inline_marks.clone(),
+                                folded_lines,
                             });
-                            unhighlighed_lines
-                                .drain(..unhighlighed_lines.len().saturating_sub(INNER_CONTEXT));
                             lines.append(&mut unhighlighed_lines);
                         }
                     }
@@ -995,17 +1884,25 @@ fn fold_body(body: Vec<DisplayLine<'_>>) -> Vec<DisplayLine<'_>> {
             }
         }
     }
+    lines.extend(unhighlighed_lines.into_iter().take(trailing_context));
 
     lines
 }
 
-fn format_body(
-    snippet: snippet::Snippet<'_>,
+fn format_body<'a>(
+    mut snippet: snippet::Snippet<'a>,
     need_empty_header: bool,
     has_footer: bool,
-    term_width: usize,
-    anonymized_line_numbers: bool,
-) -> DisplaySet<'_> {
+    renderer: &Renderer,
+) -> DisplaySet<'a> {
+    if renderer.dedup_annotations {
+        dedup_annotations(&mut snippet.annotations);
+    }
+    shorten_underlines(&mut snippet);
+    if snippet.fold && snippet.clip_to_window {
+        clip_to_window(&mut snippet, renderer);
+    }
+
     let source_len = snippet.source.len();
     if let Some(bigger) = snippet.annotations.iter().find_map(|x| {
         // Allow highlighting one past the last character in the source.
@@ -1031,8 +1928,20 @@ fn format_body(
     let mut label_right_margin = 0;
     let mut max_line_len = 0;
 
+    // An annotation at the end of a source ending in a line ending points to
+    // the (empty) line after it, so we add that line for it to be drawn on.
+    let eof_line = (snippet.source.is_empty() || snippet.source.ends_with('\n'))
+        && snippet
+            .annotations
+            .iter()
+            .any(|annotation| annotation.range.start == source_len);
+    let lines = CursorLines::new(snippet.source).chain(eof_line.then_some(("", EndLine::Eof)));
+
+    let source_has_ansi = snippet.source_has_ansi;
+    let str_width = |text: &str| CharWidths::new(source_has_ansi, renderer).str_width(text);
+
     let mut annotations = snippet.annotations;
-    for (idx, (line, end_line)) in CursorLines::new(snippet.source).enumerate() {
+    for (idx, (line, end_line)) in lines.enumerate() {
         let line_length: usize = line.len();
         let line_range = (current_index, current_index + line_length);
         let end_line_size = end_line as usize;
@@ -1052,8 +1961,8 @@ fn format_body(
             .take_while(|c| c.is_whitespace())
             .map(|c| {
                 match c {
-                    // Tabs are displayed as 4 spaces
-                    '\t' => 4,
+                    // Tabs are displayed as `tab_width` spaces
+                    '\t' => renderer.expanded_tab_width(),
                     _ => 1,
                 }
             })
@@ -1069,18 +1978,30 @@ fn format_body(
         current_index += line_length + end_line_size;
 
         // It would be nice to use filter_drain here once it's stable.
-        annotations.retain(|annotation| {
+        annotations.retain_mut(|annotation| {
             let body_idx = idx;
             let annotation_type = match annotation.level {
                 snippet::Level::Error => DisplayAnnotationType::None,
                 snippet::Level::Warning => DisplayAnnotationType::None,
+                snippet::Level::Custom(..) => DisplayAnnotationType::None,
                 _ => DisplayAnnotationType::from(annotation.level),
             };
-            let label_right = annotation.label.map_or(0, |label| label.len() + 1);
+            let label_right = annotation.label.as_ref().map_or(0, |label| {
+                label.len()
+                    + 1
+                    + label_icon(annotation.level, renderer).map_or(0, |icon| icon.len() + 1)
+                    + kind_tag(annotation.level, renderer).map_or(0, |tag| tag.len() + 1)
+            });
             match annotation.range {
                 // This handles if the annotation is on the next line. We add
                 // the `end_line_size` to account for annotating the line end.
                 Range { start, .. } if start > line_end_index + end_line_size => true,
+                // This handles if the annotation is on the end of file line
+                Range { start, .. }
+                    if eof_line && start == source_len && line_start_index < source_len =>
+                {
+                    true
+                }
                 // This handles the case where an annotation is contained
                 // within the current line including any line-end characters.
                 Range { start, end }
@@ -1094,36 +2015,47 @@ fn format_body(
                         ..
                     } = body[body_idx]
                     {
-                        let annotation_start_col = line
-                            [0..(start - line_start_index).min(line_length)]
-                            .chars()
-                            .map(|c| unicode_width::UnicodeWidthChar::width(c).unwrap_or(0))
-                            .sum::<usize>();
-                        let mut annotation_end_col = line
-                            [0..(end - line_start_index).min(line_length)]
-                            .chars()
-                            .map(|c| unicode_width::UnicodeWidthChar::width(c).unwrap_or(0))
-                            .sum::<usize>();
+                        let (annotation_start_col, mut annotation_end_col) = match &annotation
+                            .display_cols
+                        {
+                            Some(cols) => (cols.start, cols.end),
+                            None => (
+                                str_width(&line[0..(start - line_start_index).min(line_length)]),
+                                str_width(&line[0..(end - line_start_index).min(line_length)]),
+                            ),
+                        };
                         if annotation_start_col == annotation_end_col {
                             // At least highlight something
                             annotation_end_col += 1;
                         }
 
-                        span_left_margin = min(span_left_margin, annotation_start_col);
-                        span_right_margin = max(span_right_margin, annotation_end_col);
-                        label_right_margin =
-                            max(label_right_margin, annotation_end_col + label_right);
+                        if !annotation.layout_passive {
+                            span_left_margin = min(span_left_margin, annotation_start_col);
+                            span_right_margin = max(span_right_margin, annotation_end_col);
+                            label_right_margin =
+                                max(label_right_margin, annotation_end_col + label_right);
+                        }
 
                         let range = (annotation_start_col, annotation_end_col);
                         annotations.push(DisplaySourceAnnotation {
                             annotation: Annotation {
                                 annotation_type,
                                 id: None,
-                                label: format_label(annotation.label, None),
+                                id_url: None,
+                                label: format_annotation_label(
+                                    annotation.label.take(),
+                                    annotation.level,
+                                    renderer,
+                                ),
                             },
                             range,
                             annotation_type: DisplayAnnotationType::from(annotation.level),
                             annotation_part: DisplayAnnotationPart::Standalone,
+                            dimmed: annotation.dimmed,
+                            multi_line_label: annotation.multi_line_label.take(),
+                            continues_before: annotation.continues_before,
+                            continues_after: annotation.continues_after,
+                            simplified: false,
                         });
                     }
                     false
@@ -1147,6 +2079,7 @@ fn format_body(
                             inline_marks.push(DisplayMark {
                                 mark_type: DisplayMarkType::AnnotationStart,
                                 annotation_type: DisplayAnnotationType::from(annotation.level),
+                                dimmed: annotation.dimmed,
                             });
                         }
                     } else if let DisplayLine::Source {
@@ -1154,28 +2087,47 @@ fn format_body(
                         ..
                     } = body[body_idx]
                     {
-                        let annotation_start_col = line
-                            [0..(start - line_start_index).min(line_length)]
-                            .chars()
-                            .map(|c| unicode_width::UnicodeWidthChar::width(c).unwrap_or(0))
-                            .sum::<usize>();
+                        let annotation_start_col =
+                            str_width(&line[0..(start - line_start_index).min(line_length)]);
                         let annotation_end_col = annotation_start_col + 1;
 
-                        span_left_margin = min(span_left_margin, annotation_start_col);
-                        span_right_margin = max(span_right_margin, annotation_end_col);
-                        label_right_margin =
-                            max(label_right_margin, annotation_end_col + label_right);
+                        if !annotation.layout_passive {
+                            span_left_margin = min(span_left_margin, annotation_start_col);
+                            span_right_margin = max(span_right_margin, annotation_end_col);
+                            label_right_margin =
+                                max(label_right_margin, annotation_end_col + label_right);
+                        }
 
                         let range = (annotation_start_col, annotation_end_col);
+                        let label = if renderer.multiline_label_at_start {
+                            format_annotation_label(
+                                annotation.label.take(),
+                                annotation.level,
+                                renderer,
+                            )
+                        } else {
+                            vec![]
+                        };
+                        let multi_line_label = if renderer.multiline_label_at_start {
+                            annotation.multi_line_label.take()
+                        } else {
+                            None
+                        };
                         annotations.push(DisplaySourceAnnotation {
                             annotation: Annotation {
                                 annotation_type,
                                 id: None,
-                                label: vec![],
+                                id_url: None,
+                                label,
                             },
                             range,
                             annotation_type: DisplayAnnotationType::from(annotation.level),
                             annotation_part: DisplayAnnotationPart::MultilineStart,
+                            dimmed: annotation.dimmed,
+                            multi_line_label,
+                            continues_before: false,
+                            continues_after: false,
+                            simplified: false,
                         });
                     }
                     true
@@ -1193,6 +2145,7 @@ fn format_body(
                         inline_marks.push(DisplayMark {
                             mark_type: DisplayMarkType::AnnotationThrough,
                             annotation_type: DisplayAnnotationType::from(annotation.level),
+                            dimmed: annotation.dimmed,
                         });
                     }
                     true
@@ -1215,12 +2168,11 @@ fn format_body(
                         inline_marks.push(DisplayMark {
                             mark_type: DisplayMarkType::AnnotationThrough,
                             annotation_type: DisplayAnnotationType::from(annotation.level),
+                            dimmed: annotation.dimmed,
                         });
-                        let end_mark = line[0..(end - line_start_index).min(line_length)]
-                            .chars()
-                            .map(|c| unicode_width::UnicodeWidthChar::width(c).unwrap_or(0))
-                            .sum::<usize>()
-                            .saturating_sub(1);
+                        let end_mark =
+                            str_width(&line[0..(end - line_start_index).min(line_length)])
+                                .saturating_sub(1);
                         // If the annotation ends on a line-end character, we
                         // need to annotate one past the end of the line
                         let (end_mark, end_plus_one) = if end > line_end_index
@@ -1232,20 +2184,33 @@ fn format_body(
                             (end_mark, end_mark + 1)
                         };
 
-                        span_left_margin = min(span_left_margin, end_mark);
-                        span_right_margin = max(span_right_margin, end_plus_one);
-                        label_right_margin = max(label_right_margin, end_plus_one + label_right);
+                        if !annotation.layout_passive {
+                            span_left_margin = min(span_left_margin, end_mark);
+                            span_right_margin = max(span_right_margin, end_plus_one);
+                            label_right_margin =
+                                max(label_right_margin, end_plus_one + label_right);
+                        }
 
                         let range = (end_mark, end_plus_one);
                         annotations.push(DisplaySourceAnnotation {
                             annotation: Annotation {
                                 annotation_type,
                                 id: None,
-                                label: format_label(annotation.label, None),
+                                id_url: None,
+                                label: format_annotation_label(
+                                    annotation.label.take(),
+                                    annotation.level,
+                                    renderer,
+                                ),
                             },
                             range,
                             annotation_type: DisplayAnnotationType::from(annotation.level),
                             annotation_part: DisplayAnnotationPart::MultilineEnd,
+                            dimmed: annotation.dimmed,
+                            multi_line_label: annotation.multi_line_label.take(),
+                            continues_before: false,
+                            continues_after: false,
+                            simplified: false,
                         });
                     }
                     false
@@ -1255,8 +2220,13 @@ fn format_body(
         });
     }
 
+    let simplified = match renderer.max_annotations_per_line {
+        Some(max_annotations) => simplify_annotations(&mut body, max_annotations),
+        None => vec![],
+    };
+
     if snippet.fold {
-        body = fold_body(body);
+        body = fold_body(body, snippet.trailing_context, renderer);
     }
 
     if need_empty_header {
@@ -1286,34 +2256,384 @@ fn format_body(
             annotations: vec![],
         });
     }
+    if !simplified.is_empty() {
+        body.extend(format_simplified_note(simplified));
+    }
+
+    let anonymized_line_numbers = snippet
+        .anonymized_line_numbers
+        .unwrap_or(renderer.anonymized_line_numbers);
     let max_line_num_len = if anonymized_line_numbers {
-        ANONYMIZED_LINE_NUM.len()
+        display_width(renderer.anonymized_line_num, renderer)
     } else {
         current_line.to_string().len()
     };
 
-    let width_offset = 3 + max_line_num_len;
+    let max_line_num_len = if renderer.dual_line_numbers && !anonymized_line_numbers {
+        // Line numbers relative to the snippet are never wider
+        max_line_num_len * 2 + 1
+    } else {
+        max_line_num_len
+    };
+    let width_offset = renderer.indent + 3 + max_line_num_len;
+
+    if whitespace_margin == usize::MAX {
+        whitespace_margin = 0;
+    }
 
     if span_left_margin == usize::MAX {
         span_left_margin = 0;
     }
 
-    let margin = Margin::new(
+    let mut margin = Margin::new(
         whitespace_margin,
         span_left_margin,
         span_right_margin,
         label_right_margin,
-        term_width.saturating_sub(width_offset),
+        renderer
+            .content_width
+            .unwrap_or_else(|| renderer.term_width.saturating_sub(width_offset)),
         max_line_len,
     );
+    margin.keep_leading_context(span_left_margin, renderer.min_leading_context);
 
     DisplaySet {
         display_lines: body,
         margin,
+        anonymized_line_numbers,
+        excerpt_line_start: snippet.line_start,
+        source_has_ansi,
+        source_offset: 0,
+        side_label_col: renderer.side_labels.then_some(span_right_margin),
+        secondary_file: false,
+    }
+}
+
+/// Dim every annotation, counting through snippets and then footers, except
+/// for the one at `focus`
+fn dim_annotations(message: &mut snippet::Message<'_>, focus: usize, idx: &mut usize) {
+    for snippet in &mut message.snippets {
+        for annotation in &mut snippet.annotations {
+            annotation.dimmed = *idx != focus;
+            *idx += 1;
+        }
+    }
+    for footer in &mut message.footer {
+        dim_annotations(footer, focus, idx);
+    }
+}
+
+/// Fit the spans of annotations into the source, so invalid spans don't
+/// panic while rendering, see [`Renderer::render_checked`]
+///
+/// Annotations starting past the end of the source are skipped. Other spans
+/// are cut off one past the end of the source and widened to char boundaries.
+fn clamp_spans(snippet: &mut snippet::Snippet<'_>) {
+    let source = snippet.source;
+    snippet
+        .annotations
+        .retain(|annotation| annotation.range.start <= source.len());
+    for annotation in &mut snippet.annotations {
+        let range = &mut annotation.range;
+        // Pointing one past the end, at the end of the last line, is allowed
+        range.end = range.end.clamp(range.start, source.len() + 1);
+        while !source.is_char_boundary(range.start) {
+            range.start -= 1;
+        }
+        while range.end <= source.len() && !source.is_char_boundary(range.end) {
+            range.end += 1;
+        }
+    }
+}
+
+/// Shrink the spans of annotations with an
+/// [`underline_len`][snippet::Annotation::underline_len] to what is underlined
+fn shorten_underlines(snippet: &mut snippet::Snippet<'_>) {
+    for annotation in &mut snippet.annotations {
+        let (Some(len), Some(rest)) = (
+            annotation.underline_len,
+            snippet.source.get(annotation.range.start..),
+        ) else {
+            continue;
+        };
+        let underlined: usize = rest
+            .chars()
+            .take_while(|c| *c != '\r' && *c != '\n')
+            .take(len)
+            .map(char::len_utf8)
+            .sum();
+        annotation.range.end = annotation
+            .range
+            .end
+            .min(annotation.range.start + underlined);
+    }
+}
+
+/// Split each multiline annotation that spans folded lines into an annotation
+/// on its first line and one on its last line, see [`Snippet::clip_to_window`]
+///
+/// This mirrors how [`fold_body`] picks the lines to fold: runs of more than
+/// [`Renderer::max_unfolded_lines`] lines between annotated lines.
+///
+/// [`Snippet::clip_to_window`]: crate::Snippet::clip_to_window
+fn clip_to_window(snippet: &mut snippet::Snippet<'_>, renderer: &Renderer) {
+    let unfold_size = unfold_size(renderer);
+    let source = snippet.source;
+    let line_of = |offset: usize| {
+        source.as_bytes()[..offset.min(source.len())]
+            .iter()
+            .filter(|b| **b == b'\n')
+            .count()
+    };
+    let line_span = |annotation: &snippet::Annotation<'_>| {
+        let start = line_of(annotation.range.start);
+        let end = line_of(annotation.range.end.saturating_sub(1)).max(start);
+        (start, end)
+    };
+
+    let mut annotated_lines = snippet
+        .annotations
+        .iter()
+        .flat_map(|annotation| {
+            let (start, end) = line_span(annotation);
+            [start, end]
+        })
+        .collect::<Vec<_>>();
+    annotated_lines.sort_unstable();
+    annotated_lines.dedup();
+    let is_folded = |start: usize, end: usize| {
+        annotated_lines
+            .windows(2)
+            .any(|w| start <= w[0] && w[1] <= end && w[1] - w[0] - 1 > unfold_size)
+    };
+
+    let mut clipped = Vec::with_capacity(snippet.annotations.len());
+    for annotation in snippet.annotations.drain(..) {
+        let (start_line, end_line) = line_span(&annotation);
+        if start_line == end_line || !is_folded(start_line, end_line) {
+            clipped.push(annotation);
+            continue;
+        }
+
+        let first_line_end = source[annotation.range.start..]
+            .find(['\r', '\n'])
+            .map_or(source.len(), |offset| annotation.range.start + offset);
+        let mut head = annotation.clone();
+        head.range = annotation.range.start..first_line_end;
+        head.label = None;
+        head.multi_line_label = None;
+        head.continues_after = true;
+
+        let last_line_start = source[..annotation.range.end - 1]
+            .rfind('\n')
+            .map_or(0, |offset| offset + 1);
+        let last_line = &source[last_line_start..annotation.range.end];
+        let indent = last_line.len() - last_line.trim_start().len();
+        let mut tail = annotation;
+        tail.range = (last_line_start + indent).min(tail.range.end)..tail.range.end;
+        tail.continues_before = true;
+
+        clipped.push(head);
+        clipped.push(tail);
+    }
+    snippet.annotations = clipped;
+}
+
+/// Remove annotations with the same span, level, and label as an earlier one
+fn dedup_annotations(annotations: &mut Vec<snippet::Annotation<'_>>) {
+    // Sort stably by span and label, so equal annotations end up next to each
+    // other with the earliest one first
+    let mut order: Vec<usize> = (0..annotations.len()).collect();
+    order.sort_by(|&a, &b| {
+        let (a, b) = (&annotations[a], &annotations[b]);
+        (a.range.start, a.range.end, &a.label).cmp(&(b.range.start, b.range.end, &b.label))
+    });
+    let mut keep = vec![false; annotations.len()];
+    // The kept annotations with the span and label of the current one
+    let mut group: Vec<usize> = vec![];
+    for idx in order {
+        let annotation = &annotations[idx];
+        if let Some(&first) = group.first() {
+            let first = &annotations[first];
+            if first.range != annotation.range || first.label != annotation.label {
+                group.clear();
+            }
+        }
+        let is_duplicate = group.iter().any(|&other| {
+            let other = &annotations[other];
+            other.level == annotation.level && other.dimmed == annotation.dimmed
+        });
+        if !is_duplicate {
+            keep[idx] = true;
+            group.push(idx);
+        }
+    }
+    let mut keep = keep.into_iter();
+    annotations.retain(|_| keep.next().unwrap_or(true));
+}
+
+/// Move the labels of the annotations beyond `max_annotations` on each
+/// source line into a list, returning the labels that were moved
+///
+/// This bounds the amount of work (and output) for pathological inputs, like
+/// thousands of annotations on a single line. The underlines of simplified
+/// annotations are still drawn, all on one row. The parts of multiline
+/// annotations are always kept, as each connects to a line of its own, and so
+/// is the first primary annotation, to keep the point of the message. Other
+/// primary annotations come next, then the rest in order.
+fn simplify_annotations<'a>(
+    body: &mut [DisplayLine<'a>],
+    max_annotations: usize,
+) -> Vec<Vec<DisplayTextFragment<'a>>> {
+    let is_multiline = |annotation: &DisplaySourceAnnotation<'_>| {
+        matches!(
+            annotation.annotation_part,
+            DisplayAnnotationPart::MultilineStart | DisplayAnnotationPart::MultilineEnd
+        )
+    };
+    let is_primary = |annotation: &DisplaySourceAnnotation<'_>| {
+        annotation.annotation_type == DisplayAnnotationType::Error
+    };
+    let mut simplified = vec![];
+    for line in body {
+        let DisplayLine::Source { annotations, .. } = line else {
+            continue;
+        };
+        if annotations.len() <= max_annotations {
+            continue;
+        }
+        let multiline = annotations.iter().filter(|a| is_multiline(a)).count();
+        let mut available = max_annotations.saturating_sub(multiline);
+        let mut kept_primary = false;
+        for annotation in annotations
+            .iter_mut()
+            .filter(|a| !is_multiline(a) && is_primary(a))
+        {
+            if 0 < available || !kept_primary {
+                available = available.saturating_sub(1);
+                kept_primary = true;
+            } else {
+                simplified.push(simplify_annotation(annotation));
+            }
+        }
+        for annotation in annotations
+            .iter_mut()
+            .filter(|a| !is_multiline(a) && !is_primary(a))
+        {
+            if 0 < available {
+                available -= 1;
+            } else {
+                simplified.push(simplify_annotation(annotation));
+            }
+        }
+    }
+    simplified
+}
+
+/// Mark `annotation` as simplified, taking its label
+fn simplify_annotation<'a>(
+    annotation: &mut DisplaySourceAnnotation<'a>,
+) -> Vec<DisplayTextFragment<'a>> {
+    annotation.simplified = true;
+    let mut label = core::mem::take(&mut annotation.annotation.label);
+    if let Some(multi_line_label) = annotation.multi_line_label.take() {
+        label.push(DisplayTextFragment {
+            content: Cow::Owned(multi_line_label.to_plain_text()),
+            style: DisplayTextStyle::Regular,
+        });
+    }
+    label
+}
+
+fn format_simplified_note(simplified: Vec<Vec<DisplayTextFragment<'_>>>) -> Vec<DisplayLine<'_>> {
+    let title = if simplified.len() == 1 {
+        "1 annotation simplified".to_owned()
+    } else {
+        format!("{} annotations simplified", simplified.len())
+    };
+    let mut result = vec![DisplayLine::Raw(DisplayRawLine::Annotation {
+        annotation: Annotation {
+            annotation_type: DisplayAnnotationType::Note,
+            id: None,
+            id_url: None,
+            label: vec![DisplayTextFragment {
+                content: Cow::Owned(title),
+                style: DisplayTextStyle::Regular,
+            }],
+        },
+        source_aligned: true,
+        continuation: false,
+    })];
+    for label in simplified {
+        if label.iter().all(|fragment| fragment.content.is_empty()) {
+            continue;
+        }
+        result.push(DisplayLine::Raw(DisplayRawLine::Annotation {
+            annotation: Annotation {
+                annotation_type: DisplayAnnotationType::Note,
+                id: None,
+                id_url: None,
+                label,
+            },
+            source_aligned: true,
+            continuation: true,
+        }));
     }
+    result
 }
 
-fn format_repeat_char(c: char, n: usize, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+/// Draw the underlines of `annotations` on one row, with primary underlines
+/// drawn over the others
+fn format_underline_row(
+    annotations: &[&DisplaySourceAnnotation<'_>],
+    left: usize,
+    renderer: &Renderer,
+    f: &mut dyn StyledWrite,
+) -> fmt::Result {
+    let width = annotations
+        .iter()
+        .map(|annotation| annotation.range.1.saturating_sub(left))
+        .max()
+        .unwrap_or(0);
+    let mut cells: Vec<Option<&DisplaySourceAnnotation<'_>>> = vec![None; width];
+    for annotation in annotations {
+        let start = annotation.range.0.saturating_sub(left);
+        let end = annotation.range.1.saturating_sub(left).max(start);
+        for cell in &mut cells[start..end] {
+            if cell.map_or(true, |cell| {
+                cell.annotation_type != DisplayAnnotationType::Error
+                    && annotation.annotation_type == DisplayAnnotationType::Error
+            }) {
+                *cell = Some(annotation);
+            }
+        }
+    }
+    f.write_char(' ')?;
+    let mut current: Option<&Style> = None;
+    for cell in cells {
+        let style = cell.map(|annotation| {
+            source_annotation_style(&annotation.annotation_type, annotation.dimmed, renderer)
+        });
+        if style != current {
+            if let Some(current) = current {
+                f.reset_style(current)?;
+            }
+            if let Some(style) = style {
+                f.set_style(style)?;
+            }
+            current = style;
+        }
+        f.write_char(cell.map_or(' ', |annotation| {
+            underline_mark(&annotation.annotation_type, renderer)
+        }))?;
+    }
+    if let Some(current) = current {
+        f.reset_style(current)?;
+    }
+    Ok(())
+}
+
+fn format_repeat_char(c: char, n: usize, f: &mut dyn StyledWrite) -> fmt::Result {
     for _ in 0..n {
         f.write_char(c)?;
     }
@@ -1323,7 +2643,7 @@ fn format_repeat_char(c: char, n: usize, f: &mut fmt::Formatter<'_>) -> fmt::Res
 #[inline]
 fn format_annotation_type(
     annotation_type: &DisplayAnnotationType,
-    f: &mut fmt::Formatter<'_>,
+    f: &mut dyn StyledWrite,
 ) -> fmt::Result {
     match annotation_type {
         DisplayAnnotationType::Error => f.write_str(ERROR_TXT),
@@ -1331,35 +2651,441 @@ fn format_annotation_type(
         DisplayAnnotationType::Info => f.write_str(INFO_TXT),
         DisplayAnnotationType::Note => f.write_str(NOTE_TXT),
         DisplayAnnotationType::Warning => f.write_str(WARNING_TXT),
+        DisplayAnnotationType::Custom(name, _) => f.write_str(name),
         DisplayAnnotationType::None => Ok(()),
     }
 }
 
-fn annotation_type_len(annotation_type: &DisplayAnnotationType) -> usize {
+fn annotation_type_len(annotation_type: &DisplayAnnotationType, renderer: &Renderer) -> usize {
     match annotation_type {
         DisplayAnnotationType::Error => ERROR_TXT.len(),
         DisplayAnnotationType::Help => HELP_TXT.len(),
         DisplayAnnotationType::Info => INFO_TXT.len(),
         DisplayAnnotationType::Note => NOTE_TXT.len(),
         DisplayAnnotationType::Warning => WARNING_TXT.len(),
+        DisplayAnnotationType::Custom(name, _) => display_width(name, renderer),
         DisplayAnnotationType::None => 0,
     }
 }
 
 fn get_annotation_style<'a>(
-    annotation_type: &DisplayAnnotationType,
+    annotation_type: &'a DisplayAnnotationType,
     stylesheet: &'a Stylesheet,
 ) -> &'a Style {
     match annotation_type {
-        DisplayAnnotationType::Error => stylesheet.error(),
-        DisplayAnnotationType::Warning => stylesheet.warning(),
-        DisplayAnnotationType::Info => stylesheet.info(),
-        DisplayAnnotationType::Note => stylesheet.note(),
-        DisplayAnnotationType::Help => stylesheet.help(),
-        DisplayAnnotationType::None => stylesheet.none(),
+        DisplayAnnotationType::Error => &stylesheet.error,
+        DisplayAnnotationType::Warning => &stylesheet.warning,
+        DisplayAnnotationType::Info => &stylesheet.info,
+        DisplayAnnotationType::Note => &stylesheet.note,
+        DisplayAnnotationType::Help => &stylesheet.help,
+        DisplayAnnotationType::Custom(_, style) => stylesheet.custom_level_style(style),
+        DisplayAnnotationType::None => &stylesheet.none,
+    }
+}
+
+/// The display width of `ch`, taking [`Renderer::width_override`] and
+/// [`Renderer::char_width`] into account
+pub(crate) fn char_width(ch: char, renderer: &Renderer) -> Option<usize> {
+    if ch == '\t' {
+        // Tabs are expanded to spaces when rendered, but only take up columns
+        // with an explicit `Renderer::tab_width`
+        return renderer.tab_width;
+    }
+    renderer
+        .width_overrides
+        .iter()
+        .find(|(range, _)| range.contains(&ch))
+        .map(|(_, width)| *width)
+        .or_else(|| match renderer.char_width {
+            Some(width) => Some(width(ch)),
+            None => unicode_width::UnicodeWidthChar::width(ch),
+        })
+}
+
+/// Makes the text up to the next [`POP_DIRECTIONAL_FORMATTING`] display
+/// left to right, whatever its direction
+const LEFT_TO_RIGHT_OVERRIDE: char = '\u{202D}';
+const POP_DIRECTIONAL_FORMATTING: char = '\u{202C}';
+
+/// Write the separator between the line numbers and the source, after a
+/// space
+fn format_col_separator(renderer: &Renderer, f: &mut dyn StyledWrite) -> fmt::Result {
+    f.write_char(' ')?;
+    f.write_char(gutter_char(
+        renderer.separators.col_separator,
+        '|',
+        renderer,
+    ))
+}
+
+/// `ch`, if it is one column wide like the `default` it replaces in the
+/// gutter
+fn gutter_char(ch: char, default: char, renderer: &Renderer) -> char {
+    if char_width(ch, renderer) == Some(1) {
+        ch
+    } else {
+        default
+    }
+}
+
+/// Whether `ch` is in a block of a right-to-left script, like Hebrew or
+/// Arabic
+fn is_rtl(ch: char) -> bool {
+    matches!(
+        ch,
+        '\u{0590}'..='\u{08FF}'
+            | '\u{FB1D}'..='\u{FDFF}'
+            | '\u{FE70}'..='\u{FEFF}'
+            | '\u{10800}'..='\u{10FFF}'
+            | '\u{1E800}'..='\u{1EFFF}'
+    )
+}
+
+/// Where a [`DisplayList`] is written to
+///
+/// Styles and hyperlinks are passed on as calls rather than as escape
+/// sequences in the text, so outputs other than terminals don't have to read
+/// them back out of it.
+pub(crate) trait StyledWrite: Write {
+    /// Apply `style` on top of the current one, like its SGR sequence would
+    fn set_style(&mut self, style: &Style) -> fmt::Result;
+
+    /// Clear the current style, after text written with `style`
+    fn reset_style(&mut self, style: &Style) -> fmt::Result;
+
+    /// Open a hyperlink to `url`, or close the open one with `None`
+    fn set_hyperlink(&mut self, url: Option<&str>) -> fmt::Result;
+
+    /// Pass on an escape sequence of source text with
+    /// [`Snippet::source_has_ansi`][crate::Snippet::source_has_ansi]
+    fn write_escape(&mut self, sequence: &str) -> fmt::Result {
+        self.write_str(sequence)
+    }
+}
+
+/// Writes styles as ANSI escape sequences, and hyperlinks as
+/// [OSC 8](https://gist.github.com/egmontkob/eb114294efbcd5adb1944c9f3cb5feda)
+/// sequences, which take up no columns
+impl StyledWrite for fmt::Formatter<'_> {
+    fn set_style(&mut self, style: &Style) -> fmt::Result {
+        write!(self, "{}", style.render())
+    }
+
+    fn reset_style(&mut self, style: &Style) -> fmt::Result {
+        write!(self, "{}", style.render_reset())
+    }
+
+    fn set_hyperlink(&mut self, url: Option<&str>) -> fmt::Result {
+        write!(self, "\x1b]8;;{}\x1b\\", url.unwrap_or(""))
+    }
+}
+
+/// What was written to a [`StyledWrite`], to write it out again later
+#[derive(Clone, Default)]
+struct Recording {
+    pieces: Vec<Piece>,
+}
+
+/// A call on a [`StyledWrite`]
+#[derive(Clone)]
+enum Piece {
+    Text(String),
+    SetStyle(Style),
+    ResetStyle(Style),
+    Hyperlink(Option<String>),
+    Escape(String),
+}
+
+impl Recording {
+    fn replay(&self, f: &mut dyn StyledWrite) -> fmt::Result {
+        for piece in &self.pieces {
+            match piece {
+                Piece::Text(text) => f.write_str(text)?,
+                Piece::SetStyle(style) => f.set_style(style)?,
+                Piece::ResetStyle(style) => f.reset_style(style)?,
+                Piece::Hyperlink(url) => f.set_hyperlink(url.as_deref())?,
+                Piece::Escape(sequence) => f.write_escape(sequence)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a line ending is the last thing written, not counting styles
+    /// that write nothing
+    fn ends_with_newline(&self) -> bool {
+        self.pieces
+            .iter()
+            .rev()
+            .find_map(|piece| match piece {
+                Piece::Text(text) if text.is_empty() => None,
+                Piece::Text(text) => Some(text.ends_with('\n')),
+                Piece::SetStyle(style) | Piece::ResetStyle(style) if *style == Style::new() => None,
+                _ => Some(false),
+            })
+            .unwrap_or(false)
+    }
+
+    /// Split the text into lines of words, keeping each style with the word
+    /// it was set in
+    fn lines(&self) -> Vec<Vec<Recording>> {
+        let mut lines = vec![vec![Recording::default()]];
+        for piece in &self.pieces {
+            let Piece::Text(text) = piece else {
+                let word = lines.last_mut().and_then(|line| line.last_mut());
+                word.expect("there is a word").pieces.push(piece.clone());
+                continue;
+            };
+            for ch in text.chars() {
+                match ch {
+                    '\n' => lines.push(vec![Recording::default()]),
+                    ' ' => lines
+                        .last_mut()
+                        .expect("there is a line")
+                        .push(Recording::default()),
+                    _ => {
+                        let word = lines.last_mut().and_then(|line| line.last_mut());
+                        word.expect("there is a word")
+                            .push_text(ch.encode_utf8(&mut [0; 4]));
+                    }
+                }
+            }
+        }
+        lines
+    }
+
+    fn push_text(&mut self, s: &str) {
+        match self.pieces.last_mut() {
+            Some(Piece::Text(text)) => text.push_str(s),
+            _ => self.pieces.push(Piece::Text(s.to_owned())),
+        }
+    }
+
+    /// The display width of the text
+    fn width(&self, renderer: &Renderer) -> usize {
+        let mut widths = CharWidths::new(true, renderer);
+        self.pieces
+            .iter()
+            .map(|piece| match piece {
+                Piece::Text(text) => widths.str_width(text),
+                _ => 0,
+            })
+            .sum()
     }
 }
 
+impl Write for Recording {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_text(s);
+        Ok(())
+    }
+}
+
+impl StyledWrite for Recording {
+    fn set_style(&mut self, style: &Style) -> fmt::Result {
+        self.pieces.push(Piece::SetStyle(*style));
+        Ok(())
+    }
+
+    fn reset_style(&mut self, style: &Style) -> fmt::Result {
+        self.pieces.push(Piece::ResetStyle(*style));
+        Ok(())
+    }
+
+    fn set_hyperlink(&mut self, url: Option<&str>) -> fmt::Result {
+        self.pieces
+            .push(Piece::Hyperlink(url.map(ToOwned::to_owned)));
+        Ok(())
+    }
+
+    fn write_escape(&mut self, sequence: &str) -> fmt::Result {
+        self.pieces.push(Piece::Escape(sequence.to_owned()));
+        Ok(())
+    }
+}
+
+/// The display width of rendered `text`, not counting escape sequences
+pub(crate) fn display_width(text: &str, renderer: &Renderer) -> usize {
+    CharWidths::new(true, renderer).str_width(text)
+}
+
+/// Measures the display width of source text char by char
+///
+/// When the source contains ANSI escape sequences, they take up no columns.
+struct CharWidths<'r> {
+    renderer: &'r Renderer,
+    ansi: bool,
+    escape: Escape,
+}
+
+/// How far into an ANSI escape sequence [`CharWidths`] is
+#[derive(Clone, Copy)]
+enum Escape {
+    None,
+    Start,
+    Csi,
+    Osc,
+    OscEnd,
+}
+
+impl<'r> CharWidths<'r> {
+    fn new(ansi: bool, renderer: &'r Renderer) -> Self {
+        Self {
+            renderer,
+            ansi,
+            escape: Escape::None,
+        }
+    }
+
+    fn width(&mut self, ch: char) -> Option<usize> {
+        if self.is_escape(ch) {
+            return Some(0);
+        }
+        char_width(ch, self.renderer)
+    }
+
+    fn str_width(&mut self, text: &str) -> usize {
+        text.chars().map(|ch| self.width(ch).unwrap_or(0)).sum()
+    }
+
+    /// Whether `ch` is part of an escape sequence
+    fn is_escape(&mut self, ch: char) -> bool {
+        if !self.ansi {
+            return false;
+        }
+        self.escape = match (self.escape, ch) {
+            (Escape::None, '\x1b') => Escape::Start,
+            (Escape::None, _) => return false,
+            (Escape::Start, '[') => Escape::Csi,
+            (Escape::Start, ']') => Escape::Osc,
+            // A two character escape sequence
+            (Escape::Start, _) => Escape::None,
+            (Escape::Csi, '\x40'..='\x7e') => Escape::None,
+            (Escape::Csi, _) => Escape::Csi,
+            (Escape::Osc, '\x07') => Escape::None,
+            (Escape::Osc, '\x1b') => Escape::OscEnd,
+            (Escape::Osc, _) => Escape::Osc,
+            (Escape::OscEnd, _) => Escape::None,
+        };
+        true
+    }
+
+    /// Whether the last char that was part of an escape sequence ended it
+    fn escape_ended(&self) -> bool {
+        matches!(self.escape, Escape::None)
+    }
+}
+
+/// The style of an annotation, or no style when it is out of [`Renderer::focus`]
+fn source_annotation_style<'a>(
+    annotation_type: &'a DisplayAnnotationType,
+    dimmed: bool,
+    renderer: &'a Renderer,
+) -> &'a Style {
+    if dimmed {
+        &renderer.stylesheet.none
+    } else {
+        get_annotation_style(annotation_type, &renderer.stylesheet)
+    }
+}
+
+/// Open a hyperlink to `url`, if any
+fn format_hyperlink_start(url: Option<&str>, f: &mut dyn StyledWrite) -> fmt::Result {
+    match url {
+        Some(url) => f.set_hyperlink(Some(url)),
+        None => Ok(()),
+    }
+}
+
+/// Close a hyperlink opened by [`format_hyperlink_start`]
+fn format_hyperlink_end(url: Option<&str>, f: &mut dyn StyledWrite) -> fmt::Result {
+    match url {
+        Some(_) => f.set_hyperlink(None),
+        None => Ok(()),
+    }
+}
+
+/// Write the `path` of an origin header, linked to the file with
+/// [`Renderer::link_paths`]
+fn format_path(path: &str, renderer: &Renderer, f: &mut dyn StyledWrite) -> fmt::Result {
+    let url = if renderer.link_paths && !renderer.stylesheet.is_plain() {
+        file_url(path)
+    } else {
+        None
+    };
+    format_hyperlink_start(url.as_deref(), f)?;
+    f.write_str(path)?;
+    format_hyperlink_end(url.as_deref(), f)
+}
+
+/// The `file://` URL of `path`, if it is absolute or can be made so by
+/// joining it to the current directory
+fn file_url(path: &str) -> Option<String> {
+    #[cfg(feature = "std")]
+    let path = match std::path::Path::new(path) {
+        path if path.is_absolute() => path.to_path_buf(),
+        path => std::env::current_dir().ok()?.join(path),
+    };
+    #[cfg(feature = "std")]
+    let path = path.to_str().filter(|_| path.is_absolute())?;
+    #[cfg(not(feature = "std"))]
+    let path = Some(path).filter(|path| path.starts_with('/'))?;
+
+    let mut url = String::from("file://");
+    // Windows paths start with a drive letter instead
+    if !path.starts_with('/') {
+        url.push('/');
+    }
+    for byte in path.bytes() {
+        match byte {
+            b'\\' => url.push('/'),
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                url.push(char::from(byte));
+            }
+            _ => {
+                let _ = write!(url, "%{byte:02X}");
+            }
+        }
+    }
+    Some(url)
+}
+
+/// The mark to underline the span of an annotation of `annotation_type` with
+fn underline_mark(annotation_type: &DisplayAnnotationType, renderer: &Renderer) -> char {
+    let glyphs = &renderer.underline_glyphs;
+    match annotation_type {
+        DisplayAnnotationType::Error => glyphs.primary,
+        DisplayAnnotationType::Warning => glyphs.secondary,
+        DisplayAnnotationType::Info => glyphs.secondary,
+        DisplayAnnotationType::Note => glyphs.secondary,
+        DisplayAnnotationType::Help => glyphs.secondary,
+        DisplayAnnotationType::Custom(..) => glyphs.secondary,
+        DisplayAnnotationType::None => ' ',
+    }
+}
+
+/// Whether `annotation` is drawn on the same underline as `prev`, listing
+/// their labels one below the other
+///
+/// This is the case for primary single-line annotations of the same span with
+/// different labels.
+fn shares_underline(
+    prev: &DisplaySourceAnnotation<'_>,
+    annotation: &DisplaySourceAnnotation<'_>,
+) -> bool {
+    prev.range == annotation.range
+        && prev.annotation_type == DisplayAnnotationType::Error
+        && annotation.annotation_type == DisplayAnnotationType::Error
+        && prev.annotation.label != annotation.annotation.label
+        && prev.annotation_part == DisplayAnnotationPart::Standalone
+        && annotation.annotation_part == DisplayAnnotationPart::Standalone
+        && prev.dimmed == annotation.dimmed
+        && prev.continues_before == annotation.continues_before
+        && prev.continues_after == annotation.continues_after
+        && prev.multi_line_label.is_none()
+        && !is_annotation_empty(&prev.annotation)
+        && !is_annotation_empty(&annotation.annotation)
+}
+
 #[inline]
 fn is_annotation_empty(annotation: &Annotation<'_>) -> bool {
     annotation
@@ -1369,8 +3095,8 @@ fn is_annotation_empty(annotation: &Annotation<'_>) -> bool {
 }
 
 // We replace some characters so the CLI output is always consistent and underlines aligned.
+// Tabs are replaced as well, by `Renderer::tab_width` spaces.
 const OUTPUT_REPLACEMENTS: &[(char, &str)] = &[
-    ('\t', "    "),   // We do our own tab replacement
     ('\u{200D}', ""), // Replace ZWJ with nothing for consistent terminal output of grapheme clusters.
     ('\u{202A}', ""), // The following unicode text flow control characters are inconsistently
     ('\u{202B}', ""), // supported across CLIs and can cause confusion due to the bytes on disk
@@ -1383,8 +3109,17 @@ const OUTPUT_REPLACEMENTS: &[(char, &str)] = &[
     ('\u{2069}', ""),
 ];
 
-fn normalize_whitespace(str: &str) -> String {
-    let mut s = str.to_owned();
+/// `title` as it is rendered, with the same replacements as source lines
+pub(crate) fn normalize_title(title: &str, tab_width: usize) -> Cow<'_, str> {
+    if title.contains(|c| c == '\t' || OUTPUT_REPLACEMENTS.iter().any(|(r, _)| *r == c)) {
+        Cow::Owned(normalize_whitespace(title, tab_width))
+    } else {
+        Cow::Borrowed(title)
+    }
+}
+
+fn normalize_whitespace(str: &str, tab_width: usize) -> String {
+    let mut s = str.replace('\t', &" ".repeat(tab_width));
     for (c, replacement) in OUTPUT_REPLACEMENTS {
         s = s.replace(*c, replacement);
     }
@@ -0,0 +1,90 @@
+//! Rendering [`Message`]s as JSON, like rustc's `--error-format=json`
+
+use alloc::borrow::Cow;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use serde::Serialize;
+
+use crate::renderer::{level_name, Renderer};
+use crate::snippet::{Annotation, Level, Message, Snippet};
+
+#[derive(Serialize)]
+struct JsonMessage<'a> {
+    level: &'static str,
+    message: &'a str,
+    code: Option<&'a str>,
+    spans: Vec<JsonSpan<'a>>,
+    children: Vec<JsonMessage<'a>>,
+    /// The message as [`Renderer::render`] shows it, only for the top-level
+    /// message
+    rendered: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JsonSpan<'a> {
+    file_name: Option<Cow<'a, str>>,
+    /// Offsets into the whole file, see [`Snippet::source_window`]
+    byte_start: usize,
+    byte_end: usize,
+    line_start: usize,
+    line_end: usize,
+    /// 1-based, counting chars
+    column_start: usize,
+    /// 1-based, counting chars, exclusive
+    column_end: usize,
+    is_primary: bool,
+    label: Option<Cow<'a, str>>,
+}
+
+/// Serialize `msg` with its rendering by `renderer`
+pub(crate) fn render(renderer: &Renderer, msg: Message<'_>) -> String {
+    let rendered = renderer.render(msg.clone()).to_string();
+    let mut json = to_json(msg);
+    json.rendered = Some(rendered);
+    serde_json::to_string(&json).expect("serializing to a `String` can't fail")
+}
+
+fn to_json(msg: Message<'_>) -> JsonMessage<'_> {
+    let spans = msg
+        .snippets
+        .iter()
+        .flat_map(|snippet| {
+            snippet
+                .annotations
+                .iter()
+                .map(move |annotation| to_json_span(snippet, annotation))
+        })
+        .collect();
+    JsonMessage {
+        level: level_name(msg.level),
+        message: msg.title,
+        code: msg.id,
+        spans,
+        children: msg.footer.into_iter().map(to_json).collect(),
+        rendered: None,
+    }
+}
+
+fn to_json_span<'a>(snippet: &Snippet<'a>, annotation: &Annotation<'a>) -> JsonSpan<'a> {
+    let (line_start, column_start) = position(snippet, annotation.range.start);
+    let (line_end, column_end) = position(snippet, annotation.range.end);
+    JsonSpan {
+        file_name: snippet.origin.clone(),
+        byte_start: snippet.base_offset + annotation.range.start,
+        byte_end: snippet.base_offset + annotation.range.end,
+        line_start,
+        line_end,
+        column_start,
+        column_end,
+        // Like `Renderer::annotate_kinds`, primary annotations are those
+        // underlined with `^`
+        is_primary: annotation.level == Level::Error,
+        label: annotation.label.clone(),
+    }
+}
+
+/// The 1-based line and column of `offset` in the source of `snippet`
+fn position(snippet: &Snippet<'_>, offset: usize) -> (usize, usize) {
+    let (line, before) = snippet.line_prefix(offset);
+    (line, before.chars().count() + 1)
+}
@@ -1,4 +1,4 @@
-use std::cmp::{max, min};
+use core::cmp::{max, min};
 
 const ELLIPSIS_PASSING: usize = 6;
 const LONG_WHITESPACE: usize = 20;
@@ -54,6 +54,11 @@ impl Margin {
         m
     }
 
+    /// The number of columns available for the source
+    pub(crate) fn column_width(&self) -> usize {
+        self.term_width
+    }
+
     pub(crate) fn was_cut_left(&self) -> bool {
         self.computed_left > 0
     }
@@ -105,6 +110,16 @@ impl Margin {
         }
     }
 
+    /// Scroll back so at least `context` columns are shown before `span_left`,
+    /// the column of the left-most span, as long as the right-most span still fits.
+    pub(crate) fn keep_leading_context(&mut self, span_left: usize, context: usize) {
+        let left = span_left.saturating_sub(context);
+        if left < self.computed_left && self.span_right <= left + self.term_width {
+            self.computed_left = left;
+            self.computed_right = left + self.term_width;
+        }
+    }
+
     pub(crate) fn left(&self, line_len: usize) -> usize {
         min(self.computed_left, line_len)
     }
@@ -0,0 +1,90 @@
+//! Rendering [`Message`]s as Markdown, for docs and issue comments
+
+use alloc::format;
+use alloc::string::{String, ToString};
+
+use crate::renderer::display_list::DisplayList;
+use crate::renderer::{level_name, Renderer, Stylesheet};
+use crate::snippet::Message;
+
+/// Render `msg` as a bold title followed by a fenced code block
+pub(crate) fn render(renderer: &Renderer, mut msg: Message<'_>) -> String {
+    let line_ending = renderer.line_ending.as_str();
+    let mut output = String::from("**");
+    output.push_str(level_name(msg.level));
+    if let Some(id) = msg.id {
+        let id = escape(id, line_ending);
+        match msg.id_url {
+            Some(url) => output.push_str(&format!("[[{id}]({url})]")),
+            None => output.push_str(&format!("[{id}]")),
+        }
+    }
+    if output.len() > 2 {
+        output.push_str(": ");
+    }
+    // Whitespace next to the `**` would end the bold text early
+    output.push_str(&escape(renderer.display_title(&msg).trim(), line_ending));
+    output.push_str("**");
+
+    // Escape sequences would show up literally in a code block
+    let renderer = Renderer {
+        stylesheet: Stylesheet::plain(),
+        source_styler: None,
+        link_paths: false,
+        trailing_newline: false,
+        ..renderer.clone()
+    };
+    remove_id_urls(&mut msg);
+    let mut display_list = DisplayList::new(msg, &renderer);
+    display_list.remove_title();
+    let body = display_list.to_string();
+    if body.is_empty() {
+        return output;
+    }
+
+    // The fence has to be longer than any run of backticks in the source
+    let longest_backticks = body.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest_backticks.max(2) + 1);
+    for part in [line_ending, line_ending, &fence, "text", line_ending] {
+        output.push_str(part);
+    }
+    output.push_str(&body);
+    output.push_str(line_ending);
+    output.push_str(&fence);
+    output
+}
+
+/// Escape `text` so it shows up as is in Markdown, keeping its lines apart
+/// with hard line breaks
+fn escape(text: &str, line_ending: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for (idx, line) in text.lines().enumerate() {
+        if idx > 0 {
+            escaped.push('\\');
+            escaped.push_str(line_ending);
+        }
+        let line = line.trim_start();
+        // A line starting like a list item or a heading underline would end
+        // the paragraph
+        let block_marker = line
+            .find(|c: char| !c.is_ascii_digit())
+            .filter(|&idx| 0 < idx && line[idx..].starts_with(['.', ')']))
+            .or_else(|| line.starts_with(['-', '+', '=']).then_some(0));
+        for (idx, c) in line.char_indices() {
+            if matches!(
+                c,
+                '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' | '~'
+            ) || Some(idx) == block_marker
+            {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+fn remove_id_urls(msg: &mut Message<'_>) {
+    msg.id_url = None;
+    msg.footer.iter_mut().for_each(remove_id_urls);
+}
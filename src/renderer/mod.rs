@@ -10,24 +10,79 @@
 //!  let renderer = Renderer::styled();
 //!  println!("{}", renderer.render(snippet));
 
+#[cfg(any(feature = "termcolor", feature = "svg"))]
+mod ansi;
 mod display_list;
+#[cfg(feature = "serde")]
+mod json;
 mod margin;
-pub(crate) mod stylesheet;
+mod markdown;
+mod stylesheet;
+#[cfg(feature = "svg")]
+mod svg;
+#[cfg(feature = "termcolor")]
+mod write_color;
 
-use crate::snippet::Message;
+use crate::snippet::{Level, Message, Snippet, ValidationError};
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 pub use anstyle::*;
+use core::fmt::{self, Display};
+use core::ops::{Range, RangeInclusive};
+pub(crate) use display_list::display_width;
+use display_list::normalize_title;
 use display_list::DisplayList;
 use margin::Margin;
-use std::fmt::Display;
-use stylesheet::Stylesheet;
+pub use stylesheet::Stylesheet;
 
 pub const DEFAULT_TERM_WIDTH: usize = 140;
+pub const DEFAULT_TAB_WIDTH: usize = 4;
 
 /// A renderer for [`Message`]s
 #[derive(Clone, Debug)]
 pub struct Renderer {
     anonymized_line_numbers: bool,
+    anonymized_line_num: &'static str,
     term_width: usize,
+    content_width: Option<usize>,
+    cut_left: &'static str,
+    cut_right: &'static str,
+    indent: usize,
+    dual_line_numbers: bool,
+    level_note_prefix: bool,
+    focus: Option<usize>,
+    show_origin: bool,
+    label_icons: bool,
+    annotate_kinds: bool,
+    min_leading_context: usize,
+    side_labels: bool,
+    label_leaders: bool,
+    link_level: bool,
+    link_paths: bool,
+    multiline_label_at_start: bool,
+    width_overrides: Vec<(RangeInclusive<char>, usize)>,
+    char_width: Option<fn(char) -> usize>,
+    max_annotations_per_line: Option<usize>,
+    multiline_whole_line: char,
+    underline_glyphs: UnderlineGlyphs,
+    separators: Separators,
+    highlight_style: HighlightStyle,
+    inline_underline: bool,
+    normalize_labels: bool,
+    dedup_annotations: bool,
+    line_ending: LineEnding,
+    trailing_newline: bool,
+    group_by_file: bool,
+    source_styler: Option<SourceStyler>,
+    tab_width: Option<usize>,
+    wrap_labels: bool,
+    assume_ltr: bool,
+    max_unfolded_lines: usize,
+    count_folded_lines: bool,
+    strict: bool,
     stylesheet: Stylesheet,
 }
 
@@ -36,45 +91,105 @@ impl Renderer {
     pub const fn plain() -> Self {
         Self {
             anonymized_line_numbers: false,
+            anonymized_line_num: "LL",
             term_width: DEFAULT_TERM_WIDTH,
+            content_width: None,
+            cut_left: "...",
+            cut_right: "...",
+            indent: 0,
+            dual_line_numbers: false,
+            level_note_prefix: false,
+            focus: None,
+            show_origin: true,
+            label_icons: false,
+            annotate_kinds: false,
+            min_leading_context: 0,
+            side_labels: false,
+            label_leaders: false,
+            link_level: false,
+            link_paths: false,
+            multiline_label_at_start: false,
+            width_overrides: Vec::new(),
+            char_width: None,
+            max_annotations_per_line: None,
+            multiline_whole_line: '/',
+            underline_glyphs: UnderlineGlyphs::new(),
+            separators: Separators::new(),
+            highlight_style: HighlightStyle::Underline,
+            inline_underline: false,
+            normalize_labels: false,
+            dedup_annotations: true,
+            line_ending: LineEnding::Lf,
+            trailing_newline: false,
+            group_by_file: false,
+            source_styler: None,
+            tab_width: None,
+            wrap_labels: false,
+            assume_ltr: true,
+            max_unfolded_lines: 3,
+            count_folded_lines: false,
+            strict: false,
             stylesheet: Stylesheet::plain(),
         }
     }
 
+    /// No terminal styling and no `-->` headers, for annotated code blocks in
+    /// documentation
+    ///
+    /// # Example
+    ///
+    /// ```text
+    /// error: mismatched types
+    ///   |
+    /// 1 | let x: u32 = "42";
+    ///   |              ^^^^ expected `u32`, found `&str`
+    ///   |
+    /// ```
+    pub const fn plain_no_origin() -> Self {
+        Self::plain().show_origin(false)
+    }
+
     /// Default terminal styling
     ///
     /// # Note
     /// When testing styled terminal output, see the [`testing-colors` feature](crate#features)
     pub const fn styled() -> Self {
-        const USE_WINDOWS_COLORS: bool = cfg!(windows) && !cfg!(feature = "testing-colors");
-        const BRIGHT_BLUE: Style = if USE_WINDOWS_COLORS {
-            AnsiColor::BrightCyan.on_default()
-        } else {
-            AnsiColor::BrightBlue.on_default()
-        };
-        Self {
-            stylesheet: Stylesheet {
-                error: AnsiColor::BrightRed.on_default().effects(Effects::BOLD),
-                warning: if USE_WINDOWS_COLORS {
-                    AnsiColor::BrightYellow.on_default()
-                } else {
-                    AnsiColor::Yellow.on_default()
-                }
-                .effects(Effects::BOLD),
-                info: BRIGHT_BLUE.effects(Effects::BOLD),
-                note: AnsiColor::BrightGreen.on_default().effects(Effects::BOLD),
-                help: AnsiColor::BrightCyan.on_default().effects(Effects::BOLD),
-                line_no: BRIGHT_BLUE.effects(Effects::BOLD),
-                emphasis: if USE_WINDOWS_COLORS {
-                    AnsiColor::BrightWhite.on_default()
-                } else {
-                    Style::new()
-                }
-                .effects(Effects::BOLD),
-                none: Style::new(),
-            },
-            ..Self::plain()
+        let mut renderer = Self::plain();
+        renderer.stylesheet = Stylesheet::styled();
+        renderer
+    }
+
+    /// Terminal styling unless the environment asks for none
+    ///
+    /// This follows the [`NO_COLOR`](https://no-color.org/) and
+    /// [`CLICOLOR`/`CLICOLOR_FORCE`](https://bixense.com/clicolors/)
+    /// conventions, in order:
+    /// - When `NO_COLOR` is set, this is [`Renderer::plain`]
+    /// - When `CLICOLOR_FORCE` is set to anything but `0`, this is
+    ///   [`Renderer::styled`]
+    /// - When `CLICOLOR` is `0`, this is [`Renderer::plain`]
+    /// - With the `is-terminal` feature, this is [`Renderer::plain`] when
+    ///   stdout is not a terminal
+    /// - Otherwise, this is [`Renderer::styled`]
+    ///
+    /// Empty variables are treated as unset.
+    #[cfg(feature = "std")]
+    pub fn from_env() -> Self {
+        let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+        if var("NO_COLOR").is_some() {
+            return Self::plain();
+        }
+        if var("CLICOLOR_FORCE").map_or(false, |value| value != "0") {
+            return Self::styled();
+        }
+        if var("CLICOLOR").map_or(false, |value| value == "0") {
+            return Self::plain();
+        }
+        #[cfg(feature = "is-terminal")]
+        if !is_terminal::IsTerminal::is_terminal(&std::io::stdout()) {
+            return Self::plain();
         }
+        Self::styled()
     }
 
     /// Anonymize line numbers
@@ -96,67 +211,1094 @@ impl Renderer {
         self
     }
 
+    /// Set what [anonymized line numbers][Renderer::anonymized_line_numbers]
+    /// are replaced with
+    ///
+    /// Defaults to `LL`. The line number column is widened to fit longer
+    /// placeholders.
+    pub const fn anonymized_line_num_str(mut self, placeholder: &'static str) -> Self {
+        self.anonymized_line_num = placeholder;
+        self
+    }
+
     // Set the terminal width
     pub const fn term_width(mut self, term_width: usize) -> Self {
         self.term_width = term_width;
+        self.content_width = None;
+        self
+    }
+
+    /// Set the width available for the source, not counting the line number
+    /// gutter
+    ///
+    /// This is an alternative to [`Renderer::term_width`] for when the width
+    /// of the content is known rather than that of the whole output; whichever
+    /// is set last is used.
+    pub const fn content_width(mut self, content_width: usize) -> Self {
+        self.content_width = Some(content_width);
+        self
+    }
+
+    /// Set what is shown where source lines too long for the
+    /// [`term_width`][Renderer::term_width] were cut off, on the `left` and
+    /// on the `right`
+    ///
+    /// Both default to `...`. Telling them apart, e.g. with `‹` and `›`,
+    /// shows which side of a line was cut:
+    /// ```text
+    /// error: oops
+    ///   |
+    /// 1 | ‹     let y = 42; // a comment that›
+    ///   |           ^
+    /// ```
+    pub const fn cut_markers(mut self, left: &'static str, right: &'static str) -> Self {
+        self.cut_left = left;
+        self.cut_right = right;
+        self
+    }
+
+    /// Set the number of columns a tab in the source is shown as, and
+    /// counted as when placing underlines
+    ///
+    /// Without it, tabs are shown as [`DEFAULT_TAB_WIDTH`] spaces but take up
+    /// no columns when placing underlines, as in earlier releases.
+    pub const fn tab_width(mut self, tab_width: usize) -> Self {
+        self.tab_width = Some(tab_width);
+        self
+    }
+
+    /// Indent every rendered line by `indent` spaces
+    ///
+    /// This is useful when embedding a rendered [`Message`] in a list item or a
+    /// quote. The indent counts towards [`Renderer::term_width`], so less of
+    /// each source line may be shown.
+    pub const fn indent(mut self, indent: usize) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    /// Show line numbers relative to the start of each [`Snippet`]
+    /// next to the absolute ones
+    ///
+    /// # Example
+    ///
+    /// ```text
+    ///      --> src/format.rs:501:5
+    ///       |
+    /// 500 1 | fn foo() {
+    /// 501 2 |     bar();
+    ///       |     ^^^ not found in this scope
+    ///       |
+    /// ```
+    pub const fn dual_line_numbers(mut self, dual_line_numbers: bool) -> Self {
+        self.dual_line_numbers = dual_line_numbers;
+        self
+    }
+
+    /// Color the `=` in front of footers by their [`Level`]
+    /// instead of the line number color
+    pub const fn level_note_prefix(mut self, level_note_prefix: bool) -> Self {
+        self.level_note_prefix = level_note_prefix;
+        self
+    }
+
+    /// Only style the annotation at the given index, rendering all others
+    /// without color
+    ///
+    /// Annotations are counted in the order they were added, through each
+    /// [`Snippet`] of the message and then through its footers.
+    pub const fn focus(mut self, focus: Option<usize>) -> Self {
+        self.focus = focus;
+        self
+    }
+
+    /// Treat every character in `range` as being `width` columns wide
+    ///
+    /// This is useful for characters a terminal renders wider or narrower than
+    /// Unicode suggests, like the private use area glyphs of Nerd Fonts. Where
+    /// ranges overlap, the override added first wins.
+    pub fn width_override(mut self, range: RangeInclusive<char>, width: usize) -> Self {
+        self.width_overrides.push((range, width));
+        self
+    }
+
+    /// Measure how many columns wide characters are with `width`, instead of
+    /// by their Unicode width
+    ///
+    /// This is used for all alignment, e.g. to place underlines and to trim
+    /// long lines, so it can match a terminal or font with its own rules for
+    /// wide characters. Tabs are still measured as set by
+    /// [`tab_width`][Renderer::tab_width], and
+    /// [`width_override`][Renderer::width_override]s still apply.
+    pub const fn char_width(mut self, width: fn(char) -> usize) -> Self {
+        self.char_width = Some(width);
+        self
+    }
+
+    /// Put the label of a multiline annotation on the line where it starts,
+    /// rather than where it ends
+    ///
+    /// This emphasizes the opening of a block, e.g. an unclosed delimiter.
+    /// Annotations starting at the beginning of a line have no marker to put
+    /// the label on, so they keep it at the end.
+    ///
+    /// # Example
+    ///
+    /// ```text
+    /// 1 |   fn foo() {
+    ///   |  __________^ unclosed delimiter
+    /// 2 | |     bar();
+    ///   | |__________^
+    /// ```
+    pub const fn multiline_label_at_start(mut self, multiline_label_at_start: bool) -> Self {
+        self.multiline_label_at_start = multiline_label_at_start;
+        self
+    }
+
+    /// Make the level a part of the hyperlink of [`Message::id_url`][crate::Message::id_url]
+    ///
+    /// By default only the `[E0432]` of `error[E0432]` links to the URL.
+    pub const fn link_level(mut self, link_level: bool) -> Self {
+        self.link_level = link_level;
+        self
+    }
+
+    /// Make the path in `-->` headers a terminal hyperlink to the file, e.g.
+    /// to open it in an editor
+    ///
+    /// The link is a `file://` URL, which has no place for the line and
+    /// column. Relative paths are resolved against the current directory when
+    /// rendering, and are left unlinked without the `std` feature. Like other
+    /// escape sequences, the links are only rendered with styles, e.g. not
+    /// with [`Renderer::plain`].
+    pub const fn link_paths(mut self, link_paths: bool) -> Self {
+        self.link_paths = link_paths;
+        self
+    }
+
+    /// Align labels in a column to the right of all underlines, connected to
+    /// them by a leader
+    ///
+    /// Labels that don't fit in the [`term_width`][Renderer::term_width] are
+    /// rendered right after their underline as usual.
+    ///
+    /// # Example
+    ///
+    /// ```text
+    /// 1 | let x = foo(a, b);
+    ///   |         ^^^ ...... not a function
+    ///   |             - .... first argument
+    ///   |                - . second argument
+    /// ```
+    pub const fn side_labels(mut self, side_labels: bool) -> Self {
+        self.side_labels = side_labels;
+        self
+    }
+
+    /// Draw the underlines of a line with several labels on a single row,
+    /// with each label on its own row below, connected to the start of its
+    /// underline by a leader
+    ///
+    /// Labels are stacked from the right-most underline down to the
+    /// left-most, aligned in a column right of all the underlines. They are
+    /// not wrapped, and labels with line breaks or a
+    /// [`MultiLineLabel`][crate::MultiLineLabel] are rendered as usual.
+    ///
+    /// # Example
+    ///
+    /// ```text
+    /// 1 | let x = foo(a, b);
+    ///   |         ^^^ -  -
+    ///   |         |   |  . second argument
+    ///   |         |   .... first argument
+    ///   |         ........ not a function
+    /// ```
+    pub const fn label_leaders(mut self, label_leaders: bool) -> Self {
+        self.label_leaders = label_leaders;
+        self
+    }
+
+    /// Keep at least this many columns of source before the left-most
+    /// annotation when trimming long lines to fit the [`term_width`][Renderer::term_width]
+    ///
+    /// The context is given up where it would push an annotation out of view.
+    pub const fn min_leading_context(mut self, min_leading_context: usize) -> Self {
+        self.min_leading_context = min_leading_context;
+        self
+    }
+
+    /// Prefix annotation labels with an icon for their [`Level`]
+    ///
+    /// This distinguishes levels without relying on color:
+    /// `[x]` error, `[!]` warning, `[i]` info, `[*]` note and `[?]` help.
+    pub const fn label_icons(mut self, label_icons: bool) -> Self {
+        self.label_icons = label_icons;
+        self
+    }
+
+    /// Append whether an annotation is primary or context to its label
+    ///
+    /// Primary annotations, those of [`Level::Error`],
+    /// are underlined with `^` and other annotations with `-`. Tagging labels
+    /// with `[primary]` or `[context]` keeps this distinction easy to spot in
+    /// [`Renderer::plain`] output, e.g. in logs that lost their color.
+    pub const fn annotate_kinds(mut self, annotate_kinds: bool) -> Self {
+        self.annotate_kinds = annotate_kinds;
+        self
+    }
+
+    /// Wrap annotation labels at word boundaries to fit the [`term_width`][Renderer::term_width]
+    ///
+    /// Continuation lines are aligned under the start of the label. Words
+    /// longer than the available width are not broken up.
+    ///
+    /// # Example
+    ///
+    /// ```text
+    /// 1 | let x: u32 = "42";
+    ///   |              ^^^^ expected `u32` because of
+    ///   |                   the type annotation
+    /// ```
+    pub const fn wrap_labels(mut self, wrap_labels: bool) -> Self {
+        self.wrap_labels = wrap_labels;
+        self
+    }
+
+    /// Assume the terminal shows source lines left to right, as most do
+    ///
+    /// Terminals that lay out right-to-left text, like Hebrew or Arabic, in
+    /// its own direction move it away from its underlines. With this set to
+    /// `false`, source lines with such text are wrapped in a left-to-right
+    /// override (`U+202D`…`U+202C`), so each char stays above its underline.
+    ///
+    /// The default is `true`.
+    pub const fn assume_ltr(mut self, assume_ltr: bool) -> Self {
+        self.assume_ltr = assume_ltr;
+        self
+    }
+
+    /// Set the most unannotated lines between annotations that are shown
+    /// when [folding][crate::Snippet::fold]
+    ///
+    /// Longer runs of unannotated lines are collapsed into `...`, keeping one
+    /// line on either side, so runs of two lines are always shown. Defaults
+    /// to `3`.
+    pub const fn max_unfolded_lines(mut self, max_unfolded_lines: usize) -> Self {
+        self.max_unfolded_lines = max_unfolded_lines;
+        self
+    }
+
+    /// Have [`Renderer::render_checked`] reject multiline annotations that
+    /// can't be drawn without their lines crossing
+    ///
+    /// An annotation that starts on a line inside of another multiline
+    /// annotation and ends on a line after it is reported as
+    /// [`ValidationError::InterleavedMultiline`]. Other rendering methods
+    /// still draw such annotations as well as they can. The default is
+    /// `false`.
+    pub const fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Show how many lines were [folded][crate::Snippet::fold] away, e.g.
+    /// `... (12 lines)`
+    ///
+    /// The count is left out with
+    /// [`anonymized_line_numbers`][Renderer::anonymized_line_numbers], like
+    /// the line numbers it could be worked out from.
+    pub const fn count_folded_lines(mut self, count_folded_lines: bool) -> Self {
+        self.count_folded_lines = count_folded_lines;
+        self
+    }
+
+    /// Show the `-->` header with the origin of each [`Snippet`]
+    ///
+    /// This is on by default.
+    pub const fn show_origin(mut self, show_origin: bool) -> Self {
+        self.show_origin = show_origin;
+        self
+    }
+
+    /// Limit the number of annotations rendered on a single source line
+    ///
+    /// Annotations beyond the limit only have their underlines drawn, all on
+    /// one row. Their labels are listed in a `= note: N annotations
+    /// simplified` line after the snippet instead. This bounds the render time
+    /// and output size for untrusted input.
+    ///
+    /// The first primary annotation of a line and the parts of multiline
+    /// annotations always keep their labels.
+    ///
+    /// # Example
+    ///
+    /// ```text
+    ///   |
+    /// 1 | let x = a + b + c;
+    ///   |         ^ first
+    ///   |             -   -
+    ///   |
+    ///   = note: 2 annotations simplified
+    ///           second
+    ///           third
+    /// ```
+    pub const fn max_annotations_per_line(mut self, max_annotations: usize) -> Self {
+        self.max_annotations_per_line = Some(max_annotations);
+        self
+    }
+
+    /// Set the mark used for a multiline annotation starting at the beginning of a line
+    ///
+    /// Defaults to `/`.
+    ///
+    /// # Example
+    ///
+    /// ```text
+    ///   |
+    /// 2 | / fn foo() {
+    /// 3 | |     bar();
+    /// 4 | | }
+    ///   | |_^ label
+    /// ```
+    pub const fn multiline_whole_line(mut self, mark: char) -> Self {
+        self.multiline_whole_line = mark;
+        self
+    }
+
+    /// Set the glyphs annotations are drawn with
+    ///
+    /// See [`UnderlineGlyphs`]
+    pub const fn underline_glyphs(mut self, underline_glyphs: UnderlineGlyphs) -> Self {
+        self.underline_glyphs = underline_glyphs;
+        self
+    }
+
+    /// Set the separators of the gutter and the markers of the headers
+    ///
+    /// See [`Separators`]
+    pub const fn separators(mut self, separators: Separators) -> Self {
+        self.separators = separators;
+        self
+    }
+
+    /// Set how the source of single-line annotations is highlighted
+    ///
+    /// See [`HighlightStyle`]
+    pub const fn highlight_style(mut self, highlight_style: HighlightStyle) -> Self {
+        self.highlight_style = highlight_style;
+        self
+    }
+
+    /// Underline the source of single-line annotations in place (experimental)
+    ///
+    /// Each annotated character is followed by a combining low line
+    /// (`U+0332`), instead of drawing `^^^` below the source line. Labels are
+    /// still rendered below the source line.
+    ///
+    /// This depends on the terminal and font rendering combining characters;
+    /// when they don't, the source is shown without an underline.
+    pub const fn inline_underline(mut self, inline_underline: bool) -> Self {
+        self.inline_underline = inline_underline;
+        self
+    }
+
+    /// Normalize whitespace in annotation labels like in source lines
+    ///
+    /// By default, labels are rendered literally, so tabs in tabular data are
+    /// preserved. When enabled, labels go through the same normalization as
+    /// source lines: tabs are expanded to four spaces and text flow control
+    /// characters are removed.
+    pub const fn normalize_labels(mut self, normalize_labels: bool) -> Self {
+        self.normalize_labels = normalize_labels;
+        self
+    }
+
+    /// Render identical annotations only once
+    ///
+    /// Annotations on the same [`Snippet`] with the same
+    /// span, level, and label are considered identical. This is enabled by
+    /// default.
+    pub const fn dedup_annotations(mut self, dedup_annotations: bool) -> Self {
+        self.dedup_annotations = dedup_annotations;
+        self
+    }
+
+    /// Render the [`Snippet`]s of a message grouped by their
+    /// origin
+    ///
+    /// Snippets stay in the order they were added, but a snippet with the same
+    /// origin as the one right before it only shows the position in its
+    /// header. This carries over from a message to its footers, and from one
+    /// footer to the next.
+    ///
+    /// # Example
+    ///
+    /// ```text
+    ///  --> src/format.rs:1:5
+    ///   |
+    /// 1 | let x = foo();
+    ///   |     ^
+    ///   |
+    ///  ::: 3:5
+    ///   |
+    /// 3 | let y = bar();
+    ///   |     ^
+    /// ```
+    pub const fn group_by_file(mut self, group_by_file: bool) -> Self {
+        self.group_by_file = group_by_file;
+        self
+    }
+
+    /// Style the source lines, e.g. for syntax highlighting
+    ///
+    /// `styler` is called with each rendered source line and its byte range
+    /// in the source of its [`Snippet`], and returns the
+    /// [`Style`] for byte ranges of the line. Where an annotation highlights
+    /// the source, e.g. with [`HighlightStyle::Background`], its style is used
+    /// instead.
+    pub fn source_styler(
+        mut self,
+        styler: impl Fn(&str, Range<usize>) -> Vec<(Range<usize>, Style)> + Send + Sync + 'static,
+    ) -> Self {
+        self.source_styler = Some(SourceStyler(Arc::new(styler)));
+        self
+    }
+
+    /// Set the line ending to put between rendered lines
+    ///
+    /// Defaults to [`LineEnding::Lf`].
+    pub const fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// End the output with a line ending, e.g. when writing it to a file
+    ///
+    /// Nothing is added when the output already ends with one. The default
+    /// is `false`, leaving it to the caller, e.g. to `println!`.
+    pub const fn trailing_newline(mut self, trailing_newline: bool) -> Self {
+        self.trailing_newline = trailing_newline;
+        self
+    }
+
+    /// Set all output styles at once, e.g. to a theme shared as a `const`
+    ///
+    /// The setters below can still adjust single styles afterwards.
+    pub const fn stylesheet(mut self, stylesheet: Stylesheet) -> Self {
+        self.stylesheet = stylesheet;
         self
     }
 
     /// Set the output style for `error`
     pub const fn error(mut self, style: Style) -> Self {
-        self.stylesheet.error = style;
+        self.stylesheet = self.stylesheet.error(style);
         self
     }
 
     /// Set the output style for `warning`
     pub const fn warning(mut self, style: Style) -> Self {
-        self.stylesheet.warning = style;
+        self.stylesheet = self.stylesheet.warning(style);
         self
     }
 
     /// Set the output style for `info`
     pub const fn info(mut self, style: Style) -> Self {
-        self.stylesheet.info = style;
+        self.stylesheet = self.stylesheet.info(style);
         self
     }
 
     /// Set the output style for `note`
     pub const fn note(mut self, style: Style) -> Self {
-        self.stylesheet.note = style;
+        self.stylesheet = self.stylesheet.note(style);
         self
     }
 
     /// Set the output style for `help`
     pub const fn help(mut self, style: Style) -> Self {
-        self.stylesheet.help = style;
+        self.stylesheet = self.stylesheet.help(style);
         self
     }
 
     /// Set the output style for line numbers
     pub const fn line_no(mut self, style: Style) -> Self {
-        self.stylesheet.line_no = style;
+        self.stylesheet = self.stylesheet.line_no(style);
+        self
+    }
+
+    /// Set the output style for line numbers of snippets of other files than
+    /// the first one, e.g. to dim them
+    ///
+    /// Defaults to the style of [`Renderer::line_no`].
+    pub const fn secondary_line_no(mut self, style: Style) -> Self {
+        self.stylesheet = self.stylesheet.secondary_line_no(style);
         self
     }
 
     /// Set the output style for emphasis
     pub const fn emphasis(mut self, style: Style) -> Self {
-        self.stylesheet.emphasis = style;
+        self.stylesheet = self.stylesheet.emphasis(style);
         self
     }
 
     /// Set the output style for none
     pub const fn none(mut self, style: Style) -> Self {
-        self.stylesheet.none = style;
+        self.stylesheet = self.stylesheet.none(style);
         self
     }
 
     /// Render a snippet into a `Display`able object
     pub fn render<'a>(&'a self, msg: Message<'a>) -> impl Display + 'a {
-        DisplayList::new(
-            msg,
-            &self.stylesheet,
-            self.anonymized_line_numbers,
-            self.term_width,
-        )
+        self.display_list(msg)
+    }
+
+    /// Render a snippet into a `String`, reporting formatting errors
+    ///
+    /// Calling `to_string()` on the result of [`Renderer::render`] panics
+    /// when formatting fails; this returns the error instead.
+    pub fn try_render(&self, msg: Message<'_>) -> Result<String, fmt::Error> {
+        use fmt::Write as _;
+
+        let mut rendered = String::new();
+        write!(rendered, "{}", self.display_list(msg))?;
+        Ok(rendered)
+    }
+
+    /// Render a snippet into a `String`, unless the span of an annotation
+    /// doesn't fit its source
+    ///
+    /// [`Renderer::render`] makes do with such spans instead: those starting
+    /// past the end of the source are skipped, others are cut off at its end
+    /// and widened to char boundaries. When spans come from another tool,
+    /// this reports which annotation is off, counting like
+    /// [`Renderer::focus`]. See also [`Message::validate`].
+    ///
+    /// With [`Renderer::strict`], multiline annotations whose lines would
+    /// cross are reported too.
+    pub fn render_checked(&self, msg: Message<'_>) -> Result<String, RenderError> {
+        if let Some((annotation, error)) = msg.first_span_error() {
+            return Err(RenderError { annotation, error });
+        }
+        if self.strict {
+            if let Some((annotation, error)) = msg.first_interleaved_multiline() {
+                return Err(RenderError { annotation, error });
+            }
+        }
+        Ok(self.render(msg).to_string())
+    }
+
+    /// Render a snippet directly into `w`, e.g. to stream it to stderr
+    ///
+    /// The output is the same as that of [`Renderer::render`], but it is
+    /// written as it is formatted rather than collected into a `String` first.
+    /// Pairing [`Renderer::styled`] with
+    /// [`anstream::AutoStream`](https://docs.rs/anstream) gives styled output
+    /// on a terminal and plain output on a pipe.
+    #[cfg(feature = "std")]
+    pub fn render_to(&self, msg: Message<'_>, w: &mut dyn std::io::Write) -> std::io::Result<()> {
+        write!(w, "{}", self.display_list(msg))
+    }
+
+    /// Render a snippet into a `String`, reporting how it was laid out
+    ///
+    /// This can be used to detect when source lines were trimmed to fit
+    /// [`Renderer::term_width`], e.g. to offer a "view full" action.
+    pub fn render_with_layout(&self, msg: Message<'_>) -> (String, RenderLayout) {
+        let display_list = self.display_list(msg);
+        let layout = RenderLayout {
+            truncated_lines: display_list.truncated_lines(),
+        };
+        (display_list.to_string(), layout)
+    }
+
+    /// Render a snippet into a `String`, reporting how much room it takes up
+    ///
+    /// This can be used to page or scroll the output, e.g. in a TUI.
+    pub fn render_with_stats(&self, msg: Message<'_>) -> (String, RenderStats) {
+        let rendered = self.render(msg).to_string();
+        let stats = RenderStats {
+            line_count: rendered.lines().count(),
+            max_width: rendered
+                .lines()
+                .map(|line| display_width(line, self))
+                .max()
+                .unwrap_or(0),
+        };
+        (rendered, stats)
+    }
+
+    /// Render a snippet through a [`termcolor::WriteColor`]
+    ///
+    /// The styles of this renderer, e.g. those of [`Renderer::styled`], are
+    /// translated into [`termcolor::ColorSpec`]s rather than written as ANSI
+    /// escape sequences, leaving it to `out` how to show them.
+    #[cfg(feature = "termcolor")]
+    pub fn render_termcolor(
+        &self,
+        msg: Message<'_>,
+        out: &mut dyn termcolor::WriteColor,
+    ) -> std::io::Result<()> {
+        write_color::write(&self.display_list(msg), out)
+    }
+
+    /// Render a snippet as JSON, like rustc's `--error-format=json`
+    ///
+    /// The message is an object with its `level`, `message` (the title),
+    /// `code` (the id), `spans` and `children` (the footers, in the same
+    /// shape). Each span has the origin of its snippet as `file_name`, its
+    /// byte range, 1-based line and column range, `label`, and whether it
+    /// `is_primary`, i.e. of [`Level::Error`]. What [`Renderer::render`]
+    /// shows is included as `rendered`.
+    #[cfg(feature = "serde")]
+    pub fn render_json(&self, msg: Message<'_>) -> String {
+        json::render(self, msg)
+    }
+
+    /// Render a snippet as an SVG image, e.g. to embed it in web docs
+    ///
+    /// The output is laid out in cells of a monospace font, with the colors
+    /// and effects of this renderer's styles. Wide characters take up two
+    /// cells, like in a terminal. Background colors and hyperlinks are left
+    /// out. The same input always gives the same output, so it can be used
+    /// for golden tests.
+    #[cfg(feature = "svg")]
+    pub fn render_svg(&self, msg: Message<'_>) -> String {
+        svg::render(self, msg)
+    }
+
+    /// Render a snippet as Markdown, e.g. for GitHub issue comments
+    ///
+    /// The title is shown in bold, with the id linked to its
+    /// [`Message::id_url`][crate::Message::id_url], if any. The snippets and
+    /// footers are rendered like [`Renderer::plain`] would, in a fenced code
+    /// block. Styles and hyperlinks are left out of the code block.
+    ///
+    /// # Example
+    ///
+    /// ````markdown
+    /// **error[[E0432](https://doc.rust-lang.org/error_codes/E0432.html)]: unresolved import**
+    ///
+    /// ```text
+    ///  --> src/main.rs:1:5
+    ///   |
+    /// 1 | use foo::bar;
+    ///   |     ^^^ no `foo` in the root
+    ///   |
+    /// ```
+    /// ````
+    pub fn render_markdown(&self, msg: Message<'_>) -> String {
+        markdown::render(self, msg)
+    }
+
+    /// Render the message once for each of its annotations, with only that
+    /// annotation shown
+    ///
+    /// Annotations are counted like for [`Renderer::focus`]. This is useful for
+    /// stepping through the annotations of a message one at a time.
+    pub fn render_exploded(&self, msg: Message<'_>) -> Vec<String> {
+        (0..msg.annotation_count())
+            .map(|idx| {
+                let mut msg = msg.clone();
+                msg.retain_annotation(idx);
+                self.render(msg).to_string()
+            })
+            .collect()
+    }
+
+    /// The width of the line number column [`Renderer::render`] would use for
+    /// `msg`, not counting the ` |` after it
+    ///
+    /// This accounts for [`Renderer::anonymized_line_numbers`] and
+    /// [`Renderer::dual_line_numbers`], e.g. to align custom content above a
+    /// rendered message with its source lines.
+    pub fn gutter_width(&self, msg: &Message<'_>) -> usize {
+        self.display_list(msg.clone()).lineno_widths().0
+    }
+
+    /// The title of `msg` as it is rendered
+    ///
+    /// Like source lines, tabs are replaced with
+    /// [`Renderer::tab_width`] spaces and Unicode text flow control characters
+    /// are removed.
+    pub fn display_title<'a>(&self, msg: &Message<'a>) -> Cow<'a, str> {
+        normalize_title(msg.title, self.expanded_tab_width())
+    }
+
+    /// Render only the `--> path:line:col` header of `snippet`
+    ///
+    /// This is the header [`Renderer::render`] would show for the first
+    /// snippet of a message, or `None` if the snippet has no origin.
+    pub fn render_origin_line(&self, snippet: Snippet<'_>) -> Option<String> {
+        DisplayList::origin(snippet, self).map(|display_list| display_list.to_string())
+    }
+
+    /// Render each message as a [GitHub Actions workflow command](https://docs.github.com/en/actions/using-workflows/workflow-commands-for-github-actions#setting-an-error-message),
+    /// one per line
+    ///
+    /// The location is that of the first primary annotation of each message,
+    /// i.e. of [`Level::Error`], or else of the `-->`
+    /// header of its first [`Snippet`]. Messages without an origin to point
+    /// at are skipped. The title becomes the message of the command. Levels
+    /// other than [`Level::Error`] and
+    /// [`Level::Warning`] become a `notice`.
+    ///
+    /// ```text
+    /// ::error file=src/format.rs,line=51,col=5::mismatched types
+    /// ```
+    pub fn render_github_actions<'a>(
+        &self,
+        messages: impl IntoIterator<Item = Message<'a>>,
+    ) -> String {
+        let mut output = String::new();
+        for message in messages {
+            let mut snippets = message.snippets;
+            let is_primary =
+                |annotation: &crate::snippet::Annotation<'_>| annotation.level == Level::Error;
+            let snippet = match snippets
+                .iter()
+                .position(|snippet| snippet.annotations.iter().any(is_primary))
+            {
+                Some(idx) => {
+                    // Point at the primary annotation alone
+                    let mut snippet = snippets.swap_remove(idx);
+                    snippet.annotations.retain(is_primary);
+                    snippet.annotations.truncate(1);
+                    Some(snippet)
+                }
+                None => snippets.into_iter().next(),
+            };
+            let Some((path, pos)) =
+                snippet.and_then(|snippet| DisplayList::origin_position(snippet, self))
+            else {
+                continue;
+            };
+            output.push_str(match message.level {
+                Level::Error => "::error",
+                Level::Warning => "::warning",
+                _ => "::notice",
+            });
+            output.push_str(" file=");
+            output.push_str(&escape_github_property(&path));
+            if let Some((line, col)) = pos {
+                output.push_str(&format!(",line={line},col={col}"));
+            }
+            output.push_str("::");
+            output.push_str(&escape_github_data(message.title));
+            output.push('\n');
+        }
+        output
+    }
+
+    /// The number of spaces a tab is expanded to
+    pub(crate) fn expanded_tab_width(&self) -> usize {
+        self.tab_width.unwrap_or(DEFAULT_TAB_WIDTH)
+    }
+
+    fn display_list<'a>(&'a self, msg: Message<'a>) -> DisplayList<'a> {
+        DisplayList::new(msg, self)
+    }
+}
+
+pub(crate) fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Error => "error",
+        Level::Warning => "warning",
+        Level::Info => "info",
+        Level::Note => "note",
+        Level::Help => "help",
+        Level::None => "",
+        Level::Custom(custom) => custom.name,
+    }
+}
+
+/// Escape the message of a GitHub Actions workflow command
+fn escape_github_data(data: &str) -> String {
+    data.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escape a property value of a GitHub Actions workflow command
+fn escape_github_property(property: &str) -> String {
+    escape_github_data(property)
+        .replace(':', "%3A")
+        .replace(',', "%2C")
+}
+
+type StyleSourceFn = dyn Fn(&str, Range<usize>) -> Vec<(Range<usize>, Style)> + Send + Sync;
+
+/// The callback set with [`Renderer::source_styler`]
+#[derive(Clone)]
+struct SourceStyler(Arc<StyleSourceFn>);
+
+impl SourceStyler {
+    fn style(&self, line: &str, range: Range<usize>) -> Vec<(Range<usize>, Style)> {
+        (self.0)(line, range)
+    }
+}
+
+impl fmt::Debug for SourceStyler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SourceStyler")
+    }
+}
+
+/// How the source of an annotation is highlighted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HighlightStyle {
+    /// Draw an underline below the annotated source
+    #[default]
+    Underline,
+    /// Color the background of the annotated source, like a highlighter pen
+    ///
+    /// Labels are still rendered below the source line. Multiline
+    /// annotations, and annotations whose style has no foreground color
+    /// (e.g. with [`Renderer::plain`]), fall back to [`HighlightStyle::Underline`].
+    Background,
+}
+
+/// The glyphs annotations are drawn with, see [`Renderer::underline_glyphs`]
+///
+/// # Example
+///
+/// With the defaults:
+///
+/// ```text
+/// 1 |   fn foo() {
+///   |      ^^^ primary
+///   |  __________-
+/// 2 | |     bar();
+/// 3 | | }
+///   | |_- multiline
+/// ```
+///
+/// Here `^` is [`primary`][Self::primary], `-` is
+/// [`secondary`][Self::secondary], `_` is
+/// [`multiline_horizontal`][Self::multiline_horizontal] and `|` is
+/// [`multiline_vertical`][Self::multiline_vertical].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct UnderlineGlyphs {
+    pub(crate) primary: char,
+    pub(crate) secondary: char,
+    pub(crate) multiline_horizontal: char,
+    pub(crate) multiline_vertical: char,
+}
+
+impl UnderlineGlyphs {
+    /// The default glyphs
+    pub const fn new() -> Self {
+        Self {
+            primary: '^',
+            secondary: '-',
+            multiline_horizontal: '_',
+            multiline_vertical: '|',
+        }
+    }
+
+    /// Set the underline of [`Level::Error`] annotations
+    ///
+    /// Defaults to `^`.
+    pub const fn primary(mut self, glyph: char) -> Self {
+        self.primary = glyph;
+        self
+    }
+
+    /// Set the underline of annotations of the other levels
+    ///
+    /// Defaults to `-`.
+    pub const fn secondary(mut self, glyph: char) -> Self {
+        self.secondary = glyph;
+        self
+    }
+
+    /// Set the line from the start and to the end of multiline annotations
+    ///
+    /// Defaults to `_`.
+    pub const fn multiline_horizontal(mut self, glyph: char) -> Self {
+        self.multiline_horizontal = glyph;
+        self
+    }
+
+    /// Set the line connecting the lines of multiline annotations
+    ///
+    /// Defaults to `|`.
+    pub const fn multiline_vertical(mut self, glyph: char) -> Self {
+        self.multiline_vertical = glyph;
+        self
+    }
+}
+
+impl Default for UnderlineGlyphs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The separators of the gutter and the markers of the headers, see
+/// [`Renderer::separators`]
+///
+/// # Example
+///
+/// With the defaults:
+///
+/// ```text
+///  --> src/main.rs:1:4
+///   |
+/// 1 | fn foo() {}
+///   |    --- not used
+///   |
+///  ::: src/lib.rs:2:1
+///   |
+/// 2 | fn bar() {}
+///   | ----------- info: defined here
+///   |
+///   = note: `#[warn(dead_code)]` on by default
+/// ```
+///
+/// Here `|` is the [`col_separator`][Self::col_separator], `-->` the
+/// [`file_start`][Self::file_start], `:::` the
+/// [`secondary_file_start`][Self::secondary_file_start] and `=` the
+/// [`note_separator`][Self::note_separator].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Separators {
+    pub(crate) col_separator: char,
+    pub(crate) file_start: &'static str,
+    pub(crate) secondary_file_start: &'static str,
+    pub(crate) note_separator: char,
+}
+
+impl Separators {
+    /// The default separators
+    pub const fn new() -> Self {
+        Self {
+            col_separator: '|',
+            file_start: "-->",
+            secondary_file_start: ":::",
+            note_separator: '=',
+        }
+    }
+
+    /// Set the separator between the line numbers and the source
+    ///
+    /// Defaults to `|`. To keep the gutter aligned, a char that isn't one
+    /// column wide is rendered as the default.
+    pub const fn col_separator(mut self, separator: char) -> Self {
+        self.col_separator = separator;
+        self
+    }
+
+    /// Set the marker of the header of the first snippet
+    ///
+    /// Defaults to `-->`.
+    pub const fn file_start(mut self, marker: &'static str) -> Self {
+        self.file_start = marker;
+        self
+    }
+
+    /// Set the marker of the headers of the following snippets
+    ///
+    /// Defaults to `:::`.
+    pub const fn secondary_file_start(mut self, marker: &'static str) -> Self {
+        self.secondary_file_start = marker;
+        self
+    }
+
+    /// Set the separator before footers in the gutter
+    ///
+    /// Defaults to `=`. To keep the gutter aligned, a char that isn't one
+    /// column wide is rendered as the default.
+    pub const fn note_separator(mut self, separator: char) -> Self {
+        self.note_separator = separator;
+        self
+    }
+}
+
+impl Default for Separators {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The line ending put between rendered lines, see [`Renderer::line_ending`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`
+    #[default]
+    Lf,
+    /// `\r\n`, e.g. for Windows consumers
+    CrLf,
+}
+
+impl LineEnding {
+    /// The line ending as it is written
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+        }
+    }
+}
+
+/// How a [`Message`] was laid out by [`Renderer::render_with_layout`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RenderLayout {
+    truncated_lines: Vec<usize>,
+}
+
+impl RenderLayout {
+    /// Whether any source line was trimmed to fit the terminal width
+    pub fn is_truncated(&self) -> bool {
+        !self.truncated_lines.is_empty()
+    }
+
+    /// The line numbers of the source lines that were trimmed
+    pub fn truncated_lines(&self) -> &[usize] {
+        &self.truncated_lines
+    }
+}
+
+/// An annotation that [`Renderer::render_checked`] found to be invalid
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenderError {
+    annotation: usize,
+    error: ValidationError,
+}
+
+impl RenderError {
+    /// The index of the annotation, counting through snippets and then footers
+    pub fn annotation(&self) -> usize {
+        self.annotation
+    }
+
+    /// What is wrong with the span of the annotation
+    pub fn error(&self) -> &ValidationError {
+        &self.error
+    }
+}
+
+impl Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, at index {}", self.error, self.annotation)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RenderError {}
+
+/// How much room a [`Message`] takes up, see [`Renderer::render_with_stats`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    line_count: usize,
+    max_width: usize,
+}
+
+impl RenderStats {
+    /// The number of lines rendered
+    pub fn line_count(&self) -> usize {
+        self.line_count
+    }
+
+    /// The display width of the widest line, not counting escape sequences
+    pub fn max_width(&self) -> usize {
+        self.max_width
     }
 }
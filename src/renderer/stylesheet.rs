@@ -1,13 +1,30 @@
-use anstyle::Style;
+use anstyle::{AnsiColor, Effects, Style};
 
-#[derive(Clone, Copy, Debug)]
-pub(crate) struct Stylesheet {
+/// The styles of a [`Renderer`][crate::Renderer], see
+/// [`Renderer::stylesheet`][crate::Renderer::stylesheet]
+///
+/// As all of its methods are `const`, a theme can be shared as a `const`:
+/// ```
+/// use annotate_snippets::renderer::{AnsiColor, Stylesheet};
+/// use annotate_snippets::Renderer;
+///
+/// const THEME: Stylesheet = Stylesheet::styled()
+///     .error(AnsiColor::Magenta.on_default())
+///     .line_no(AnsiColor::BrightBlack.on_default());
+///
+/// let renderer = Renderer::plain().stylesheet(THEME);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Stylesheet {
     pub(crate) error: Style,
     pub(crate) warning: Style,
     pub(crate) info: Style,
     pub(crate) note: Style,
     pub(crate) help: Style,
     pub(crate) line_no: Style,
+    /// The style of the line numbers of snippets of other files than the
+    /// first one, if not that of `line_no`
+    pub(crate) secondary_line_no: Option<Style>,
     pub(crate) emphasis: Style,
     pub(crate) none: Style,
 }
@@ -19,7 +36,8 @@ impl Default for Stylesheet {
 }
 
 impl Stylesheet {
-    pub(crate) const fn plain() -> Self {
+    /// No styles, like [`Renderer::plain`][crate::Renderer::plain]
+    pub const fn plain() -> Self {
         Self {
             error: Style::new(),
             warning: Style::new(),
@@ -27,42 +45,117 @@ impl Stylesheet {
             note: Style::new(),
             help: Style::new(),
             line_no: Style::new(),
+            secondary_line_no: None,
             emphasis: Style::new(),
             none: Style::new(),
         }
     }
-}
 
-impl Stylesheet {
-    pub(crate) fn error(&self) -> &Style {
-        &self.error
+    /// The default terminal styles, like
+    /// [`Renderer::styled`][crate::Renderer::styled]
+    pub const fn styled() -> Self {
+        const USE_WINDOWS_COLORS: bool = cfg!(windows) && !cfg!(feature = "testing-colors");
+        const BRIGHT_BLUE: Style = if USE_WINDOWS_COLORS {
+            AnsiColor::BrightCyan.on_default()
+        } else {
+            AnsiColor::BrightBlue.on_default()
+        };
+        Self {
+            error: AnsiColor::BrightRed.on_default().effects(Effects::BOLD),
+            warning: if USE_WINDOWS_COLORS {
+                AnsiColor::BrightYellow.on_default()
+            } else {
+                AnsiColor::Yellow.on_default()
+            }
+            .effects(Effects::BOLD),
+            info: BRIGHT_BLUE.effects(Effects::BOLD),
+            note: AnsiColor::BrightGreen.on_default().effects(Effects::BOLD),
+            help: AnsiColor::BrightCyan.on_default().effects(Effects::BOLD),
+            line_no: BRIGHT_BLUE.effects(Effects::BOLD),
+            secondary_line_no: None,
+            emphasis: if USE_WINDOWS_COLORS {
+                AnsiColor::BrightWhite.on_default()
+            } else {
+                Style::new()
+            }
+            .effects(Effects::BOLD),
+            none: Style::new(),
+        }
+    }
+
+    /// Set the output style for `error`
+    pub const fn error(mut self, style: Style) -> Self {
+        self.error = style;
+        self
+    }
+
+    /// Set the output style for `warning`
+    pub const fn warning(mut self, style: Style) -> Self {
+        self.warning = style;
+        self
+    }
+
+    /// Set the output style for `info`
+    pub const fn info(mut self, style: Style) -> Self {
+        self.info = style;
+        self
+    }
+
+    /// Set the output style for `note`
+    pub const fn note(mut self, style: Style) -> Self {
+        self.note = style;
+        self
+    }
+
+    /// Set the output style for `help`
+    pub const fn help(mut self, style: Style) -> Self {
+        self.help = style;
+        self
     }
 
-    pub(crate) fn warning(&self) -> &Style {
-        &self.warning
+    /// Set the output style for line numbers
+    pub const fn line_no(mut self, style: Style) -> Self {
+        self.line_no = style;
+        self
     }
 
-    pub(crate) fn info(&self) -> &Style {
-        &self.info
+    /// Set the output style for line numbers of snippets of other files than
+    /// the first one, e.g. to dim them
+    ///
+    /// Defaults to the style of [`Stylesheet::line_no`].
+    pub const fn secondary_line_no(mut self, style: Style) -> Self {
+        self.secondary_line_no = Some(style);
+        self
     }
 
-    pub(crate) fn note(&self) -> &Style {
-        &self.note
+    /// Set the output style for emphasis
+    pub const fn emphasis(mut self, style: Style) -> Self {
+        self.emphasis = style;
+        self
     }
 
-    pub(crate) fn help(&self) -> &Style {
-        &self.help
+    /// Set the output style for none
+    pub const fn none(mut self, style: Style) -> Self {
+        self.none = style;
+        self
     }
 
-    pub(crate) fn line_no(&self) -> &Style {
-        &self.line_no
+    pub(crate) fn secondary_line_no_style(&self) -> &Style {
+        self.secondary_line_no.as_ref().unwrap_or(&self.line_no)
     }
 
-    pub(crate) fn emphasis(&self) -> &Style {
-        &self.emphasis
+    /// Whether this has no styles at all, like [`Stylesheet::plain`]
+    pub(crate) fn is_plain(&self) -> bool {
+        *self == Self::plain()
     }
 
-    pub(crate) fn none(&self) -> &Style {
-        &self.none
+    /// The style of a [`Level::custom`][crate::Level::custom], which is left
+    /// out when nothing else is styled
+    pub(crate) fn custom_level_style<'a>(&'a self, style: &'a Style) -> &'a Style {
+        if self.is_plain() {
+            &self.none
+        } else {
+            style
+        }
     }
 }
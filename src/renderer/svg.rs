@@ -0,0 +1,272 @@
+//! Rendering [`Message`]s as SVG, for embedding in web pages and docs
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use anstyle::{Ansi256Color, Color, Effects, Style};
+use core::fmt::{self, Write as _};
+
+use crate::renderer::ansi::{self, Sequence};
+use crate::renderer::display_list::{char_width, StyledWrite};
+use crate::renderer::Renderer;
+use crate::snippet::Message;
+
+/// Padding around the text, in pixels
+const PADDING: usize = 10;
+/// The width of a cell of the monospace font, in tenths of a pixel
+const CELL_WIDTH: usize = 84;
+/// The height of a line, in pixels
+const LINE_HEIGHT: usize = 18;
+const FG: &str = "#AAAAAA";
+const BG: &str = "#000000";
+/// The 16 colors of the terminal, with the bright ones from `8`
+const PALETTE: [(&str, &str); 16] = [
+    ("black", "#000000"),
+    ("red", "#AA0000"),
+    ("green", "#00AA00"),
+    ("yellow", "#AA5500"),
+    ("blue", "#0000AA"),
+    ("magenta", "#AA00AA"),
+    ("cyan", "#00AAAA"),
+    ("white", "#AAAAAA"),
+    ("bright-black", "#555555"),
+    ("bright-red", "#FF5555"),
+    ("bright-green", "#55FF55"),
+    ("bright-yellow", "#FFFF55"),
+    ("bright-blue", "#5555FF"),
+    ("bright-magenta", "#FF55FF"),
+    ("bright-cyan", "#55FFFF"),
+    ("bright-white", "#FFFFFF"),
+];
+
+/// A run of text on a line, starting at cell `col`
+struct Run {
+    col: usize,
+    style: Style,
+    text: String,
+}
+
+/// Render `msg` with the styles of `renderer` and lay it out as SVG
+pub(crate) fn render(renderer: &Renderer, msg: Message<'_>) -> String {
+    // A trailing line ending would only add an empty row
+    let renderer = &Renderer {
+        trailing_newline: false,
+        ..renderer.clone()
+    };
+    let mut layout = Layout {
+        renderer,
+        lines: vec![(Vec::new(), 0)],
+        style: Style::new(),
+        new_run: true,
+    };
+    let _ = renderer.display_list(msg).write_to(&mut layout);
+    let lines = layout.lines;
+
+    // Class names to colors, sorted to keep the output deterministic
+    let mut colors = BTreeMap::new();
+    for run in lines.iter().flat_map(|(runs, _)| runs) {
+        if let Some(color) = run.style.get_fg_color() {
+            let (name, value) = color_class(color);
+            colors.insert(name, value);
+        }
+    }
+    let width_cells = lines.iter().map(|(_, width)| *width).max().unwrap_or(0);
+    let width = PADDING * 2 + (width_cells * CELL_WIDTH + 9) / 10;
+    let height = PADDING * 2 + lines.len() * LINE_HEIGHT;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg width="{width}px" height="{height}px" xmlns="http://www.w3.org/2000/svg">"#
+    );
+    svg.push_str("  <style>\n");
+    let _ = writeln!(svg, "    .fg {{ fill: {FG} }}");
+    let _ = writeln!(svg, "    .bg {{ background: {BG} }}");
+    for (name, value) in &colors {
+        let _ = writeln!(svg, "    .{name} {{ fill: {value} }}");
+    }
+    svg.push_str(
+        r#"    .container {
+      padding: 0 10px;
+      line-height: 18px;
+    }
+    .bold { font-weight: bold; }
+    .dimmed { opacity: 0.7; }
+    .italic { font-style: italic; }
+    .underline { text-decoration: underline; }
+    .strikethrough { text-decoration: line-through; }
+    tspan {
+      font: 14px SFMono-Regular, Consolas, Liberation Mono, Menlo, monospace;
+      white-space: pre;
+      line-height: 18px;
+    }
+  </style>
+
+  <rect width="100%" height="100%" y="0" rx="4.5" class="bg" />
+
+  <text xml:space="preserve" class="container fg">
+"#,
+    );
+    for (idx, (runs, _)) in lines.iter().enumerate() {
+        let y = PADDING + (idx + 1) * LINE_HEIGHT;
+        let _ = write!(svg, r#"    <tspan x="{PADDING}px" y="{y}px">"#);
+        for run in runs {
+            let x = PADDING * 10 + run.col * CELL_WIDTH;
+            let _ = write!(svg, r#"<tspan x="{}.{}px""#, x / 10, x % 10);
+            let classes = classes(run.style);
+            if !classes.is_empty() {
+                let _ = write!(svg, r#" class="{}""#, classes.join(" "));
+            }
+            let _ = write!(svg, ">{}</tspan>", escape_xml(&run.text));
+        }
+        svg.push_str("</tspan>\n");
+    }
+    svg.push_str("  </text>\n\n</svg>\n");
+    svg
+}
+
+/// Lays out what is written into lines of styled runs, with the width of
+/// each line in cells
+///
+/// A new run is started after every char that isn't one cell wide, so wide
+/// glyphs take up two cells whatever the font does.
+struct Layout<'r> {
+    renderer: &'r Renderer,
+    lines: Vec<(Vec<Run>, usize)>,
+    style: Style,
+    new_run: bool,
+}
+
+impl<'r> Layout<'r> {
+    fn restyle(&mut self, style: Style) {
+        self.style = style;
+        self.new_run = true;
+    }
+}
+
+impl<'r> fmt::Write for Layout<'r> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for ch in s.chars() {
+            let (runs, col) = self.lines.last_mut().expect("there is a line");
+            match ch {
+                '\n' => {
+                    self.lines.push((Vec::new(), 0));
+                    self.new_run = true;
+                    continue;
+                }
+                '\r' => continue,
+                _ => {}
+            }
+            if self.new_run || runs.is_empty() {
+                runs.push(Run {
+                    col: *col,
+                    style: self.style,
+                    text: String::new(),
+                });
+            }
+            let run = runs.last_mut().expect("a run was just pushed");
+            let width = if ch == '\t' {
+                let width = self.renderer.expanded_tab_width();
+                run.text.push_str(&" ".repeat(width));
+                width
+            } else {
+                run.text.push(ch);
+                char_width(ch, self.renderer).unwrap_or(0)
+            };
+            *col += width;
+            self.new_run = width != 1;
+        }
+        Ok(())
+    }
+}
+
+impl<'r> StyledWrite for Layout<'r> {
+    fn set_style(&mut self, style: &Style) -> fmt::Result {
+        if *style != Style::new() {
+            self.restyle(ansi::overlay(self.style, style));
+        }
+        Ok(())
+    }
+
+    fn reset_style(&mut self, style: &Style) -> fmt::Result {
+        if *style != Style::new() {
+            self.restyle(Style::new());
+        }
+        Ok(())
+    }
+
+    fn set_hyperlink(&mut self, _url: Option<&str>) -> fmt::Result {
+        // Links can't be followed from an image
+        Ok(())
+    }
+
+    fn write_escape(&mut self, sequence: &str) -> fmt::Result {
+        if let Sequence::Style(style) = ansi::parse(sequence, self.style) {
+            self.restyle(style);
+        }
+        Ok(())
+    }
+}
+
+fn classes(style: Style) -> Vec<String> {
+    let mut classes = Vec::new();
+    if let Some(color) = style.get_fg_color() {
+        classes.push(color_class(color).0);
+    }
+    let effects = style.get_effects();
+    for (effect, class) in [
+        (Effects::BOLD, "bold"),
+        (Effects::DIMMED, "dimmed"),
+        (Effects::ITALIC, "italic"),
+        (Effects::UNDERLINE, "underline"),
+        (Effects::STRIKETHROUGH, "strikethrough"),
+    ] {
+        if effects.contains(effect) {
+            classes.push(class.into());
+        }
+    }
+    classes
+}
+
+/// The class name for text of `color`, and the color as hex
+fn color_class(color: Color) -> (String, String) {
+    let index = match color {
+        Color::Ansi(color) => Ansi256Color::from_ansi(color).index(),
+        Color::Ansi256(color) => color.index(),
+        Color::Rgb(color) => {
+            let hex = format!("{:02X}{:02X}{:02X}", color.r(), color.g(), color.b());
+            return (format!("fg-rgb-{hex}"), format!("#{hex}"));
+        }
+    };
+    if let Some((name, value)) = PALETTE.get(usize::from(index)) {
+        return (format!("fg-{name}"), (*value).into());
+    }
+    let (r, g, b) = if index < 232 {
+        // The 6x6x6 color cube
+        let level = |value: u8| if value == 0 { 0 } else { 55 + value * 40 };
+        let index = index - 16;
+        (level(index / 36), level(index / 6 % 6), level(index % 6))
+    } else {
+        let gray = 8 + (index - 232) * 10;
+        (gray, gray, gray)
+    };
+    (
+        format!("fg-ansi256-{index:03}"),
+        format!("#{r:02X}{g:02X}{b:02X}"),
+    )
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,164 @@
+//! Writing rendered output through a [`termcolor::WriteColor`]
+
+use anstyle::{Ansi256Color, Effects, Style};
+use core::fmt;
+use std::io;
+use termcolor::{Color, ColorSpec, HyperlinkSpec, WriteColor};
+
+use crate::renderer::ansi::{self, Sequence};
+use crate::renderer::display_list::{DisplayList, StyledWrite};
+
+/// Write `display_list` to `out`, with its styles set as [`ColorSpec`]s and
+/// its hyperlinks as [`HyperlinkSpec`]s
+///
+/// This leaves it to `out` how (and whether) they are shown.
+pub(crate) fn write(display_list: &DisplayList<'_>, out: &mut dyn WriteColor) -> io::Result<()> {
+    let mut writer = ColorWriter {
+        out,
+        style: Style::new(),
+        changed: false,
+        error: None,
+    };
+    if display_list.write_to(&mut writer).is_err() {
+        return Err(writer
+            .error
+            .unwrap_or_else(|| io::Error::new(io::ErrorKind::Other, "formatter error")));
+    }
+    if writer.changed {
+        writer.out.reset()?;
+    }
+    Ok(())
+}
+
+/// Follows the style of what is written, setting it on `out` once there is
+/// text to style
+struct ColorWriter<'a> {
+    out: &'a mut dyn WriteColor,
+    style: Style,
+    /// Whether the style was changed since text was last written
+    changed: bool,
+    /// What `out` failed with, as a [`fmt::Error`] can't carry it
+    error: Option<io::Error>,
+}
+
+impl<'a> ColorWriter<'a> {
+    fn restyle(&mut self, style: Style) {
+        self.style = style;
+        self.changed = true;
+    }
+
+    fn io(&mut self, result: io::Result<()>) -> fmt::Result {
+        result.map_err(|error| {
+            self.error = Some(error);
+            fmt::Error
+        })
+    }
+}
+
+impl<'a> fmt::Write for ColorWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.is_empty() {
+            return Ok(());
+        }
+        if self.changed {
+            let spec = color_spec(self.style);
+            let result = if spec.is_none() {
+                self.out.reset()
+            } else {
+                self.out.set_color(&spec)
+            };
+            self.io(result)?;
+            self.changed = false;
+        }
+        let result = self.out.write_all(s.as_bytes());
+        self.io(result)
+    }
+}
+
+impl<'a> StyledWrite for ColorWriter<'a> {
+    fn set_style(&mut self, style: &Style) -> fmt::Result {
+        if *style != Style::new() {
+            self.restyle(ansi::overlay(self.style, style));
+        }
+        Ok(())
+    }
+
+    fn reset_style(&mut self, style: &Style) -> fmt::Result {
+        if *style != Style::new() {
+            self.restyle(Style::new());
+        }
+        Ok(())
+    }
+
+    fn set_hyperlink(&mut self, url: Option<&str>) -> fmt::Result {
+        let spec = match url {
+            Some(url) => HyperlinkSpec::open(url.as_bytes()),
+            None => HyperlinkSpec::close(),
+        };
+        let result = self.out.set_hyperlink(&spec);
+        self.io(result)
+    }
+
+    fn write_escape(&mut self, sequence: &str) -> fmt::Result {
+        match ansi::parse(sequence, self.style) {
+            Sequence::Style(style) => self.restyle(style),
+            Sequence::Hyperlink(url) => self.set_hyperlink(url)?,
+            Sequence::Other => {}
+        }
+        Ok(())
+    }
+}
+
+fn color_spec(style: Style) -> ColorSpec {
+    let effects = style.get_effects();
+    let mut spec = ColorSpec::new();
+    spec.set_bold(effects.contains(Effects::BOLD))
+        .set_dimmed(effects.contains(Effects::DIMMED))
+        .set_italic(effects.contains(Effects::ITALIC))
+        .set_underline(effects.contains(Effects::UNDERLINE))
+        .set_strikethrough(effects.contains(Effects::STRIKETHROUGH));
+    if let Some(fg) = style.get_fg_color() {
+        // Bright colors are the intense variants of the 8 basic ones
+        let (color, intense) = match fg {
+            anstyle::Color::Ansi(color) => {
+                let index = Ansi256Color::from_ansi(color).index();
+                (ansi_color(index % 8), index >= 8)
+            }
+            color => (color_of(color), false),
+        };
+        spec.set_fg(Some(color)).set_intense(intense);
+    }
+    spec.set_bg(style.get_bg_color().map(|bg| match bg {
+        anstyle::Color::Ansi(color) => {
+            let index = Ansi256Color::from_ansi(color).index();
+            if index < 8 {
+                ansi_color(index)
+            } else {
+                Color::Ansi256(index)
+            }
+        }
+        color => color_of(color),
+    }));
+    spec
+}
+
+fn color_of(color: anstyle::Color) -> Color {
+    match color {
+        anstyle::Color::Ansi(color) => Color::Ansi256(Ansi256Color::from_ansi(color).index()),
+        anstyle::Color::Ansi256(color) => Color::Ansi256(color.index()),
+        anstyle::Color::Rgb(color) => Color::Rgb(color.r(), color.g(), color.b()),
+    }
+}
+
+fn ansi_color(code: u8) -> Color {
+    match code {
+        0 => Color::Black,
+        1 => Color::Red,
+        2 => Color::Green,
+        3 => Color::Yellow,
+        4 => Color::Blue,
+        5 => Color::Magenta,
+        6 => Color::Cyan,
+        _ => Color::White,
+    }
+}
@@ -10,26 +10,84 @@
 //!     .snippet(Snippet::source("Faa").line_start(129).origin("src/display.rs"));
 //! ```
 
-use std::ops::Range;
+use alloc::borrow::Cow;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use anstyle::Style;
+use core::fmt;
+use core::ops::{Range, RangeInclusive};
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
 
 /// Primary structure provided for formatting
 ///
 /// See [`Level::title`] to create a [`Message`]
-#[derive(Debug)]
+///
+/// Messages that were built the same way are equal, so duplicates can be
+/// dropped, e.g. by collecting them into a `HashSet`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Message<'a> {
     pub(crate) level: Level,
     pub(crate) id: Option<&'a str>,
+    pub(crate) id_url: Option<&'a str>,
     pub(crate) title: &'a str,
     pub(crate) snippets: Vec<Snippet<'a>>,
     pub(crate) footer: Vec<Message<'a>>,
 }
 
 impl<'a> Message<'a> {
+    /// Create an error [`Message`] with the given `title`
+    ///
+    /// Shorthand for `Level::Error.title(title)`
+    pub fn error(title: &'a str) -> Self {
+        Level::Error.title(title)
+    }
+
+    /// Create a warning [`Message`] with the given `title`
+    ///
+    /// Shorthand for `Level::Warning.title(title)`
+    pub fn warning(title: &'a str) -> Self {
+        Level::Warning.title(title)
+    }
+
+    /// Create an info [`Message`] with the given `title`
+    ///
+    /// Shorthand for `Level::Info.title(title)`
+    pub fn info(title: &'a str) -> Self {
+        Level::Info.title(title)
+    }
+
+    /// Create a note [`Message`] with the given `title`
+    ///
+    /// Shorthand for `Level::Note.title(title)`
+    pub fn note(title: &'a str) -> Self {
+        Level::Note.title(title)
+    }
+
+    /// Create a help [`Message`] with the given `title`
+    ///
+    /// Shorthand for `Level::Help.title(title)`
+    pub fn help(title: &'a str) -> Self {
+        Level::Help.title(title)
+    }
+
     pub fn id(mut self, id: &'a str) -> Self {
         self.id = Some(id);
         self
     }
 
+    /// Make the [`id`][Message::id] a terminal hyperlink to `url`, e.g. to an
+    /// explanation of the error code
+    ///
+    /// The link is only rendered with styles, e.g. not with
+    /// [`Renderer::plain`][crate::Renderer::plain]. See also
+    /// [`Renderer::link_level`][crate::Renderer::link_level].
+    pub fn id_url(mut self, url: &'a str) -> Self {
+        self.id_url = Some(url);
+        self
+    }
+
     pub fn snippet(mut self, slice: Snippet<'a>) -> Self {
         self.snippets.push(slice);
         self
@@ -49,22 +107,246 @@ impl<'a> Message<'a> {
         self.footer.extend(footer);
         self
     }
+
+    pub fn get_level(&self) -> Level {
+        self.level
+    }
+
+    /// The title as it was given, see
+    /// [`Renderer::display_title`][crate::Renderer::display_title] for how it
+    /// is rendered
+    pub fn get_title(&self) -> &'a str {
+        self.title
+    }
+
+    pub fn get_id(&self) -> Option<&'a str> {
+        self.id
+    }
+
+    pub fn get_snippets(&self) -> &[Snippet<'a>] {
+        &self.snippets
+    }
+
+    pub fn get_footers(&self) -> &[Message<'a>] {
+        &self.footer
+    }
+
+    /// Check every [`Snippet`] and [`Annotation`], including those in footers,
+    /// for problems that would otherwise only show up while rendering
+    ///
+    /// All problems are reported at once rather than just the first.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = vec![];
+        self.collect_errors(&mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Merge the [`Annotation`]s of each [`Snippet`] that have the same label,
+    /// including those in footers
+    ///
+    /// The earliest annotation of each label is kept, with the number of
+    /// others appended to its label, e.g. `expected here (and 2 more)`.
+    /// Unlike [`Renderer::dedup_annotations`][crate::Renderer::dedup_annotations],
+    /// this merges annotations regardless of their span.
+    pub fn group_by_label(mut self) -> Self {
+        for snippet in &mut self.snippets {
+            snippet.group_annotations_by_label();
+        }
+        self.footer = self
+            .footer
+            .into_iter()
+            .map(Message::group_by_label)
+            .collect();
+        self
+    }
+
+    /// The 1-based line numbers each [`Snippet`] of this message will show,
+    /// keyed by the index of the snippet
+    ///
+    /// With [`Snippet::fold`], this is only the lines from the first to the
+    /// last [`Annotation`], though some in between may still be folded away.
+    /// Footers are not included.
+    pub fn referenced_lines(&self) -> Vec<(usize, Range<usize>)> {
+        self.snippets
+            .iter()
+            .enumerate()
+            .map(|(idx, snippet)| (idx, snippet.referenced_lines()))
+            .collect()
+    }
+
+    /// The number of annotations in this message, including those in footers
+    pub(crate) fn annotation_count(&self) -> usize {
+        self.snippets
+            .iter()
+            .map(|snippet| snippet.annotations.len())
+            .sum::<usize>()
+            + self
+                .footer
+                .iter()
+                .map(Message::annotation_count)
+                .sum::<usize>()
+    }
+
+    /// Remove every annotation except the one at `keep`, counting through
+    /// snippets and then footers
+    pub(crate) fn retain_annotation(&mut self, keep: usize) {
+        let mut idx = 0;
+        self.retain_annotation_from(keep, &mut idx);
+    }
+
+    fn retain_annotation_from(&mut self, keep: usize, idx: &mut usize) {
+        for snippet in &mut self.snippets {
+            snippet.annotations.retain(|_| {
+                let retain = *idx == keep;
+                *idx += 1;
+                retain
+            });
+        }
+        for footer in &mut self.footer {
+            footer.retain_annotation_from(keep, idx);
+        }
+    }
+
+    /// The first annotation whose lines would cross those of another
+    /// multiline annotation, by its index counting through snippets and then
+    /// footers
+    pub(crate) fn first_interleaved_multiline(&self) -> Option<(usize, ValidationError)> {
+        let mut idx = 0;
+        self.first_interleaved_multiline_from(&mut idx)
+    }
+
+    fn first_interleaved_multiline_from(
+        &self,
+        idx: &mut usize,
+    ) -> Option<(usize, ValidationError)> {
+        for snippet in &self.snippets {
+            for annotation in &snippet.annotations {
+                if let Some(error) = snippet.interleaved_multiline(annotation) {
+                    return Some((*idx, error));
+                }
+                *idx += 1;
+            }
+        }
+        self.footer
+            .iter()
+            .find_map(|footer| footer.first_interleaved_multiline_from(idx))
+    }
+
+    fn collect_errors(&self, errors: &mut Vec<ValidationError>) {
+        for snippet in &self.snippets {
+            if snippet.line_start == 0 {
+                errors.push(ValidationError::ZeroLineStart);
+            }
+            errors.extend(
+                snippet
+                    .annotations
+                    .iter()
+                    .filter_map(|annotation| annotation.span_error(snippet.source)),
+            );
+        }
+        for footer in &self.footer {
+            footer.collect_errors(errors);
+        }
+    }
+
+    /// The first annotation with a span that doesn't fit its source, by its
+    /// index counting through snippets and then footers
+    pub(crate) fn first_span_error(&self) -> Option<(usize, ValidationError)> {
+        let mut idx = 0;
+        self.first_span_error_from(&mut idx)
+    }
+
+    fn first_span_error_from(&self, idx: &mut usize) -> Option<(usize, ValidationError)> {
+        for snippet in &self.snippets {
+            for annotation in &snippet.annotations {
+                if let Some(error) = annotation.span_error(snippet.source) {
+                    return Some((*idx, error));
+                }
+                *idx += 1;
+            }
+        }
+        self.footer
+            .iter()
+            .find_map(|footer| footer.first_span_error_from(idx))
+    }
+}
+
+/// A problem found by [`Message::validate`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ValidationError {
+    /// A [`Snippet`] starts at line `0`; line numbers start at `1`
+    ZeroLineStart,
+    /// An [`Annotation`] starts after it ends
+    InvertedRange { range: Range<usize> },
+    /// An [`Annotation`] extends past the end of its [`Snippet`]'s source
+    ///
+    /// A span may end one past the end of the source, to point at the end of
+    /// the last line.
+    OutOfBounds {
+        range: Range<usize>,
+        source_len: usize,
+    },
+    /// An [`Annotation`] starts or ends inside of a character
+    NotCharBoundary { range: Range<usize> },
+    /// An [`Annotation`] starts on a line inside of the multiline
+    /// annotation `other` and ends on a line after it, so the lines drawn
+    /// for them would cross, see [`Renderer::strict`][crate::Renderer::strict]
+    InterleavedMultiline {
+        range: Range<usize>,
+        other: Range<usize>,
+    },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ZeroLineStart => f.write_str("snippet line numbers start at 1, not 0"),
+            Self::InvertedRange { range } => {
+                write!(f, "annotation {range:?} starts after it ends")
+            }
+            Self::OutOfBounds { range, source_len } => write!(
+                f,
+                "annotation {range:?} is out of bounds for a source of length {source_len}"
+            ),
+            Self::NotCharBoundary { range } => {
+                write!(f, "annotation {range:?} is not on a char boundary")
+            }
+            Self::InterleavedMultiline { range, other } => write!(
+                f,
+                "annotation {range:?} crosses the multiline annotation {other:?}"
+            ),
+        }
+    }
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for ValidationError {}
+
 /// Structure containing the slice of text to be annotated and
 /// basic information about the location of the slice.
 ///
 /// One `Snippet` is meant to represent a single, continuous,
 /// slice of source code that you want to annotate.
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Snippet<'a> {
-    pub(crate) origin: Option<&'a str>,
+    pub(crate) origin: Option<Cow<'a, str>>,
     pub(crate) line_start: usize,
 
     pub(crate) source: &'a str,
+    /// The offset of `source` in the file, see [`Snippet::source_window`]
+    pub(crate) base_offset: usize,
     pub(crate) annotations: Vec<Annotation<'a>>,
 
     pub(crate) fold: bool,
+    pub(crate) clip_to_window: bool,
+    pub(crate) trailing_context: usize,
+    pub(crate) anonymized_line_numbers: Option<bool>,
+    pub(crate) source_has_ansi: bool,
 }
 
 impl<'a> Snippet<'a> {
@@ -73,8 +355,39 @@ impl<'a> Snippet<'a> {
             origin: None,
             line_start: 1,
             source,
+            base_offset: 0,
             annotations: vec![],
             fold: false,
+            clip_to_window: false,
+            trailing_context: 0,
+            anonymized_line_numbers: None,
+            source_has_ansi: false,
+        }
+    }
+
+    /// A window of a larger file, starting at byte `base_offset` of it
+    ///
+    /// The spans of annotations added to the snippet are offsets into the
+    /// whole file and are moved into the window, so only the part of the file
+    /// that is shown has to be passed. Spans that end before the window are
+    /// dropped, and spans that start before it are clipped to its start.
+    /// Spans past the window are like any other spans past the source, see
+    /// [`Message::validate`].
+    /// [`line_start`][Snippet::line_start] is the line number of the first
+    /// line of the window.
+    ///
+    /// ```
+    /// # use annotate_snippets::{Level, Snippet};
+    /// let file = "fn main() {}\nfn foo() {}\n";
+    /// let snippet = Snippet::source_window(&file[13..], 13)
+    ///     .line_start(2)
+    ///     .annotation(Level::Error.span(16..19));
+    /// assert_eq!(snippet.get_annotations()[0].get_range(), 3..6);
+    /// ```
+    pub fn source_window(source: &'a str, base_offset: usize) -> Self {
+        Self {
+            base_offset,
+            ..Self::source(source)
         }
     }
 
@@ -84,17 +397,214 @@ impl<'a> Snippet<'a> {
     }
 
     pub fn origin(mut self, origin: &'a str) -> Self {
-        self.origin = Some(origin);
+        self.origin = Some(Cow::Borrowed(origin));
+        self
+    }
+
+    /// Set the origin to a file system path
+    ///
+    /// Non-UTF-8 path components are displayed with
+    /// [`U+FFFD REPLACEMENT CHARACTER`][char::REPLACEMENT_CHARACTER].
+    #[cfg(feature = "std")]
+    pub fn origin_path(mut self, path: &'a Path) -> Self {
+        self.origin = Some(path.to_string_lossy());
         self
     }
 
+    /// Set the origin to an owned file system path
+    ///
+    /// See [`Snippet::origin_path`]
+    #[cfg(feature = "std")]
+    pub fn path_buf(mut self, path: PathBuf) -> Self {
+        let origin = path
+            .into_os_string()
+            .into_string()
+            .unwrap_or_else(|path| path.to_string_lossy().into_owned());
+        self.origin = Some(Cow::Owned(origin));
+        self
+    }
+
+    /// Add an annotation
+    ///
+    /// The `-->` header points at the start of the first annotation that was
+    /// added and isn't [passive][Annotation::layout_passive], so add the main
+    /// annotation first.
     pub fn annotation(mut self, annotation: Annotation<'a>) -> Self {
-        self.annotations.push(annotation);
+        self.push_annotation(annotation);
         self
     }
 
     pub fn annotations(mut self, annotation: impl IntoIterator<Item = Annotation<'a>>) -> Self {
-        self.annotations.extend(annotation);
+        for annotation in annotation {
+            self.push_annotation(annotation);
+        }
+        self
+    }
+
+    /// Add `annotation`, with its span moved into the
+    /// [window][Snippet::source_window]
+    fn push_annotation(&mut self, mut annotation: Annotation<'a>) {
+        let base = self.base_offset;
+        if annotation.range.start < base && annotation.range.end <= base {
+            return;
+        }
+        annotation.range =
+            annotation.range.start.max(base) - base..annotation.range.end.saturating_sub(base);
+        self.annotations.push(annotation);
+    }
+
+    fn group_annotations_by_label(&mut self) {
+        let mut groups: Vec<(Annotation<'a>, usize)> = vec![];
+        for annotation in self.annotations.drain(..) {
+            let group = groups
+                .iter_mut()
+                .find(|(first, _)| annotation.label.is_some() && first.label == annotation.label);
+            match group {
+                Some((first, more)) => {
+                    *more += 1;
+                    if annotation.range.start < first.range.start {
+                        *first = annotation;
+                    }
+                }
+                None => groups.push((annotation, 0)),
+            }
+        }
+        self.annotations = groups
+            .into_iter()
+            .map(|(mut annotation, more)| {
+                if more > 0 {
+                    annotation.label = annotation
+                        .label
+                        .map(|label| Cow::Owned(format!("{label} (and {more} more)")));
+                }
+                annotation
+            })
+            .collect();
+    }
+
+    fn referenced_lines(&self) -> Range<usize> {
+        let source_len = self.source.len();
+        let (start, end) = if self.fold {
+            let start = self.annotations.iter().map(|ann| ann.range.start).min();
+            let end = self
+                .annotations
+                .iter()
+                .map(|ann| ann.range.end)
+                .max()
+                .unwrap_or(source_len)
+                .min(source_len);
+            // The line ending of the line `trailing_context` lines further
+            let end = self.source[end..]
+                .match_indices('\n')
+                .map(|(offset, _)| end + offset)
+                .nth(self.trailing_context)
+                .unwrap_or_else(|| source_len - usize::from(self.source.ends_with('\n')));
+            (start.unwrap_or(0), end)
+        } else if self.source.ends_with('\n')
+            && !self
+                .annotations
+                .iter()
+                .any(|ann| ann.range.start == source_len)
+        {
+            // There is no line after the trailing line ending to show
+            (0, source_len - 1)
+        } else {
+            (0, source_len)
+        };
+        let line_of = |offset: usize| {
+            let offset = offset.min(source_len);
+            self.line_start
+                + self.source.as_bytes()[..offset]
+                    .iter()
+                    .filter(|b| **b == b'\n')
+                    .count()
+        };
+        line_of(start)..line_of(end) + 1
+    }
+
+    /// Add an annotation for each of `spans`, all with the same `level` and `label`
+    pub fn annotate_each(
+        mut self,
+        spans: impl IntoIterator<Item = Range<usize>>,
+        level: Level,
+        label: &'a str,
+    ) -> Self {
+        for span in spans {
+            self.push_annotation(level.span(span).label(label));
+        }
+        self
+    }
+
+    /// Annotate the whole line at `line_index`, counting from `0` at the start
+    /// of the `source`
+    ///
+    /// The annotation covers the line without its trailing whitespace; on a
+    /// blank line it points at the first column. Nothing is annotated if the
+    /// `source` has no such line.
+    pub fn annotate_line(mut self, line_index: usize, level: Level, label: &'a str) -> Self {
+        let mut start = 0;
+        for (idx, line) in self.source.split_inclusive('\n').enumerate() {
+            if idx == line_index {
+                let content = line.trim_end();
+                // A blank line is annotated through its line ending, which
+                // keeps the annotation from being drawn on the previous line
+                let end = start + content.len().max(1);
+                self.annotations.push(level.span(start..end).label(label));
+                break;
+            }
+            start += line.len();
+        }
+        self
+    }
+
+    /// Annotate the whole `source`, e.g. for a problem with an entire file
+    ///
+    /// A trailing line ending is left out, so the annotation ends on the last
+    /// character of the last line. With [`Snippet::fold`], the lines in between
+    /// may be folded away, but the first and last lines are always shown.
+    pub fn annotate_all(mut self, level: Level, label: &'a str) -> Self {
+        let end = self.source.trim_end_matches(['\r', '\n']).len();
+        self.annotations.push(level.span(0..end).label(label));
+        self
+    }
+
+    /// Annotate every occurrence of `pat` in the `source`
+    ///
+    /// Matches are found left-to-right and don't overlap, e.g. `"aa"` matches
+    /// `"aaa"` once, at the start. An empty `pat` matches nothing.
+    ///
+    /// This is a convenience for quick tooling; prefer [`Snippet::annotation`]
+    /// with precise spans from your parser.
+    pub fn annotate_matches(mut self, pat: &str, level: Level, label: &'a str) -> Self {
+        if pat.is_empty() {
+            return self;
+        }
+        let matches = self
+            .source
+            .match_indices(pat)
+            .map(|(start, matched)| level.span(start..start + matched.len()).label(label));
+        self.annotations.extend(matches);
+        self
+    }
+
+    /// Pass ANSI escape sequences in the `source` through, e.g. when
+    /// annotating colored terminal output
+    ///
+    /// The escape sequences are not counted towards the width of the source,
+    /// so annotations line up with the visible text. Annotation spans are
+    /// still byte offsets into the `source`, escape sequences included.
+    pub fn source_has_ansi(mut self, source_has_ansi: bool) -> Self {
+        self.source_has_ansi = source_has_ansi;
+        self
+    }
+
+    /// Anonymize line numbers for this snippet
+    ///
+    /// This overrides [`Renderer::anonymized_line_numbers`][crate::Renderer::anonymized_line_numbers]
+    /// for this snippet only, e.g. to anonymize generated code while showing
+    /// real line numbers for user code.
+    pub fn anonymized(mut self, anonymized_line_numbers: bool) -> Self {
+        self.anonymized_line_numbers = Some(anonymized_line_numbers);
         self
     }
 
@@ -103,28 +613,299 @@ impl<'a> Snippet<'a> {
         self.fold = fold;
         self
     }
+
+    /// With [`Snippet::fold`], also show the `lines` after the last
+    /// [`Annotation`], e.g. a closing brace
+    ///
+    /// Defaults to `0`.
+    pub fn trailing_context(mut self, lines: usize) -> Self {
+        self.trailing_context = lines;
+        self
+    }
+
+    /// With [`Snippet::fold`], draw a multiline [`Annotation`] whose lines are
+    /// partly folded away as an underline on its first and last lines, capped
+    /// with `…` where it continues into the folded lines
+    ///
+    /// The label is shown on the last line, as for other multiline annotations.
+    pub fn clip_to_window(mut self, clip_to_window: bool) -> Self {
+        self.clip_to_window = clip_to_window;
+        self
+    }
+
+    pub fn get_source(&self) -> &'a str {
+        self.source
+    }
+
+    pub fn get_line_start(&self) -> usize {
+        self.line_start
+    }
+
+    /// The origin as it is shown in the `-->` header, if any
+    pub fn get_origin(&self) -> Option<&str> {
+        self.origin.as_deref()
+    }
+
+    pub fn get_annotations(&self) -> &[Annotation<'a>] {
+        &self.annotations
+    }
+
+    /// The [`Location`]s of the start and end of `span` in the source
+    ///
+    /// Lines are counted from [`line_start`][Snippet::line_start] and
+    /// columns in chars from `1`, like in the `-->` header. Like the spans of
+    /// annotations, `span` is moved into the [window][Snippet::source_window].
+    /// Offsets outside of the source are taken to be at its nearest end, and
+    /// offsets inside a char at its start.
+    pub fn locate(&self, span: Range<usize>) -> (Location, Location) {
+        (self.location(span.start), self.location(span.end))
+    }
+
+    fn location(&self, offset: usize) -> Location {
+        let (line, before) = self.line_prefix(offset.saturating_sub(self.base_offset));
+        Location {
+            line,
+            column: before.chars().count() + 1,
+        }
+    }
+
+    /// The line number of `offset` into the source, counted from
+    /// [`line_start`][Snippet::line_start], and the text before it on its line
+    ///
+    /// Offsets past the source are taken to be at its end, and offsets inside
+    /// a char at its start.
+    pub(crate) fn line_prefix(&self, offset: usize) -> (usize, &'a str) {
+        let offset = offset.min(self.source.len());
+        let before = (0..=offset)
+            .rev()
+            .find_map(|offset| self.source.get(..offset))
+            .unwrap_or_default();
+        let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+        (
+            self.line_start + before.matches('\n').count(),
+            &before[line_start..],
+        )
+    }
+
+    /// The error for `annotation` if it starts on a line inside of another
+    /// multiline annotation and ends on a line after it
+    fn interleaved_multiline(&self, annotation: &Annotation<'_>) -> Option<ValidationError> {
+        let line = |offset: usize| {
+            self.source.as_bytes()[..offset.min(self.source.len())]
+                .iter()
+                .filter(|byte| **byte == b'\n')
+                .count()
+        };
+        // The line of the last byte of a span, rather than the one after it
+        let lines = |range: &Range<usize>| {
+            (
+                line(range.start),
+                line(range.end.saturating_sub(1).max(range.start)),
+            )
+        };
+        let range = &annotation.range;
+        let (start, end) = lines(range);
+        self.annotations
+            .iter()
+            .find(|other| {
+                let (other_start, other_end) = lines(&other.range);
+                other.range.start < range.start
+                    && range.start < other.range.end
+                    && other.range.end < range.end
+                    && other_start < start
+                    && start <= other_end
+                    && other_end < end
+            })
+            .map(|other| ValidationError::InterleavedMultiline {
+                range: range.clone(),
+                other: other.range.clone(),
+            })
+    }
+}
+
+/// A line and column in a [`Snippet`], see [`Snippet::locate`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
 }
 
 /// An annotation for a [`Snippet`].
 ///
 /// See [`Level::span`] to create a [`Annotation`]
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Annotation<'a> {
     /// The byte range of the annotation in the `source` string
     pub(crate) range: Range<usize>,
-    pub(crate) label: Option<&'a str>,
+    pub(crate) label: Option<Cow<'a, str>>,
     pub(crate) level: Level,
+    pub(crate) dimmed: bool,
+    /// The display columns to draw the annotation at, if already known
+    pub(crate) display_cols: Option<Range<usize>>,
+    pub(crate) underline_len: Option<usize>,
+    pub(crate) multi_line_label: Option<MultiLineLabel<'a>>,
+    /// Whether the annotation was clipped at its start, see [`Snippet::clip_to_window`]
+    pub(crate) continues_before: bool,
+    /// Whether the annotation was clipped at its end, see [`Snippet::clip_to_window`]
+    pub(crate) continues_after: bool,
+    pub(crate) layout_passive: bool,
 }
 
 impl<'a> Annotation<'a> {
+    /// Set the label shown next to the annotation
+    ///
+    /// A label with line breaks is continued on the following lines, aligned
+    /// under its start.
     pub fn label(mut self, label: &'a str) -> Self {
-        self.label = Some(label);
+        self.label = Some(Cow::Borrowed(label));
+        self
+    }
+
+    /// Show `label` as aligned columns below the annotation, e.g. to line up
+    /// `expected: ()` and `found: i32`
+    ///
+    /// This is shown in addition to any [`Annotation::label`]. Where the
+    /// columns don't fit in the [`Renderer::term_width`][crate::Renderer::term_width],
+    /// they are shown as plain text after the label instead.
+    pub fn multi_line_label(mut self, label: MultiLineLabel<'a>) -> Self {
+        self.multi_line_label = Some(label);
+        self
+    }
+
+    /// Draw the annotation without letting it affect the layout
+    ///
+    /// A passive annotation, e.g. a far-away "see also", is not taken into
+    /// account when trimming long lines to fit the
+    /// [`Renderer::term_width`][crate::Renderer::term_width], nor is it
+    /// pointed at by the `-->` header of its [`Snippet`].
+    pub fn layout_passive(mut self, layout_passive: bool) -> Self {
+        self.layout_passive = layout_passive;
+        self
+    }
+
+    /// Only underline the first `len` characters of the span
+    ///
+    /// This keeps the emphasis short for large spans, e.g. a caret at the
+    /// start of a long statement, while the label still describes the whole
+    /// span. The underline stops at the end of the first line of the span,
+    /// and never extends past the span itself.
+    pub fn underline_len(mut self, len: usize) -> Self {
+        self.underline_len = Some(len);
+        self
+    }
+
+    /// Draw the annotation at these display columns of its line, instead of
+    /// computing them from the source
+    ///
+    /// This skips measuring the width of the source line for tools that have
+    /// already done so. The columns are used verbatim, so they must agree with
+    /// how the line is rendered (e.g. tabs are 4 columns wide) or the
+    /// annotation will be misaligned.
+    ///
+    /// Only annotations on a single line are affected.
+    pub fn display_columns(mut self, cols: Range<usize>) -> Self {
+        self.display_cols = Some(cols);
+        self
+    }
+
+    pub fn get_level(&self) -> Level {
+        self.level
+    }
+
+    /// The byte range of the annotation in the source of its [`Snippet`]
+    pub fn get_range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    pub fn get_label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// The problem with the span of this annotation in `source`, if any
+    fn span_error(&self, source: &str) -> Option<ValidationError> {
+        let range = &self.range;
+        if range.start > range.end {
+            Some(ValidationError::InvertedRange {
+                range: range.clone(),
+            })
+        } else if source.len() + 1 < range.end {
+            // Pointing one past the end, at the end of the last line, is allowed
+            Some(ValidationError::OutOfBounds {
+                range: range.clone(),
+                source_len: source.len(),
+            })
+        } else if !source.is_char_boundary(range.start)
+            || (range.end <= source.len() && !source.is_char_boundary(range.end))
+        {
+            Some(ValidationError::NotCharBoundary {
+                range: range.clone(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// A label of rows of columns, see [`Annotation::multi_line_label`]
+///
+/// Each column is padded to its widest cell, so the cells of a column line up.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MultiLineLabel<'a> {
+    pub(crate) rows: Vec<Vec<&'a str>>,
+}
+
+impl<'a> MultiLineLabel<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn row(mut self, cells: impl IntoIterator<Item = &'a str>) -> Self {
+        self.rows.push(cells.into_iter().collect());
         self
     }
+
+    /// Each row with its cells padded to the width of their column
+    pub(crate) fn aligned_rows(&self, renderer: &crate::Renderer) -> Vec<String> {
+        let mut widths: Vec<usize> = vec![];
+        for row in &self.rows {
+            for (idx, cell) in row.iter().enumerate() {
+                let width = crate::renderer::display_width(cell, renderer);
+                match widths.get_mut(idx) {
+                    Some(max_width) => *max_width = (*max_width).max(width),
+                    None => widths.push(width),
+                }
+            }
+        }
+        self.rows
+            .iter()
+            .map(|row| {
+                let mut line = String::new();
+                for (idx, cell) in row.iter().enumerate() {
+                    if idx + 1 == row.len() {
+                        line.push_str(cell);
+                    } else {
+                        let padding = widths[idx] - crate::renderer::display_width(cell, renderer);
+                        line.push_str(cell);
+                        line.extend(core::iter::repeat(' ').take(padding + 1));
+                    }
+                }
+                line
+            })
+            .collect()
+    }
+
+    /// The rows on a single line, for when the columns don't fit
+    pub(crate) fn to_plain_text(&self) -> String {
+        self.rows
+            .iter()
+            .map(|row| row.join(" "))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
 }
 
 /// Types of annotations.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Level {
     /// Error annotations are displayed using red color and "^" character.
     Error,
@@ -133,13 +914,39 @@ pub enum Level {
     Info,
     Note,
     Help,
+    /// No level, for a title without an `error:`-like prefix.
+    ///
+    /// Annotations at this level are not underlined.
+    None,
+    /// A level of your own, see [`Level::custom`]
+    Custom(CustomLevel),
+}
+
+/// The name and style of a [`Level::custom`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CustomLevel {
+    pub(crate) name: &'static str,
+    pub(crate) style: Style,
 }
 
 impl Level {
+    /// A level named `name`, shown in `style`, for severities beyond the
+    /// built-in ones
+    ///
+    /// The name takes the place of `error` or `warning` in the title, and
+    /// annotations at this level are underlined like a [`Level::Warning`].
+    /// `style` is left out when the renderer's
+    /// [`Stylesheet`][crate::renderer::Stylesheet] has no styles, like that
+    /// of [`Renderer::plain`][crate::Renderer::plain].
+    pub const fn custom(name: &'static str, style: Style) -> Self {
+        Self::Custom(CustomLevel { name, style })
+    }
+
     pub fn title(self, title: &str) -> Message<'_> {
         Message {
             level: self,
             id: None,
+            id_url: None,
             title,
             snippets: vec![],
             footer: vec![],
@@ -152,6 +959,24 @@ impl Level {
             range: span,
             label: None,
             level: self,
+            dimmed: false,
+            display_cols: None,
+            underline_len: None,
+            multi_line_label: None,
+            continues_before: false,
+            continues_after: false,
+            layout_passive: false,
         }
     }
+
+    /// Create a [`Annotation`] with the given inclusive span for a [`Snippet`]
+    ///
+    /// This is the same as [`Level::span`] with `start..end + 1`, for spans
+    /// that include their last byte, e.g. token positions from a lexer. The
+    /// end is the last byte of the span, so for a multi-byte char, it is the
+    /// last byte of that char.
+    pub fn span_inclusive<'a>(self, span: RangeInclusive<usize>) -> Annotation<'a> {
+        let (start, end) = span.into_inner();
+        self.span(start..end.saturating_add(1))
+    }
 }
@@ -126,14 +126,6 @@ impl<'a> From<AnnotationDef<'a>> for Annotation<'a> {
     }
 }
 
-#[derive(Serialize, Deserialize)]
-pub(crate) struct LabelDef<'a> {
-    #[serde(with = "LevelDef")]
-    pub(crate) level: Level,
-    #[serde(borrow)]
-    pub(crate) label: &'a str,
-}
-
 #[allow(dead_code)]
 #[derive(Serialize, Deserialize)]
 #[serde(remote = "Level")]
@@ -143,6 +135,9 @@ enum LevelDef {
     Info,
     Note,
     Help,
+    None,
+    #[serde(skip)]
+    Custom(#[serde(skip)] annotate_snippets::CustomLevel),
 }
 
 #[derive(Default, Deserialize)]
@@ -1,4 +1,9 @@
-use annotate_snippets::{Level, Renderer, Snippet};
+use annotate_snippets::renderer::{
+    AnsiColor, HighlightStyle, LineEnding, Separators, Stylesheet, UnderlineGlyphs,
+};
+use annotate_snippets::{
+    Level, Location, Message, MultiLineLabel, Renderer, Snippet, ValidationError,
+};
 
 use snapbox::{assert_data_eq, str};
 
@@ -200,7 +205,6 @@ error
 }
 
 #[test]
-#[should_panic]
 fn test_i26() {
     let source = "short";
     let label = "label";
@@ -209,8 +213,15 @@ fn test_i26() {
             .line_start(0)
             .annotation(Level::Error.span(0..source.len() + 2).label(label)),
     );
+    let expected = str![[r#"
+error
+ |
+0 | short
+ | ^^^^^ label
+ |
+"#]];
     let renderer = Renderer::plain();
-    let _ = renderer.render(input).to_string();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
 }
 
 #[test]
@@ -732,3 +743,2788 @@ error
     let renderer = Renderer::plain().anonymized_line_numbers(false);
     assert_data_eq!(renderer.render(input).to_string(), expected);
 }
+
+#[test]
+fn max_annotations_per_line() {
+    let source = "let x = a + b + c;";
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source(source)
+            .origin("file/path")
+            .annotation(Level::Error.span(8..9).label("first"))
+            .annotation(Level::Warning.span(12..13).label("second"))
+            .annotation(Level::Warning.span(16..17).label("third")),
+    );
+    let expected = str![[r#"
+error: oops
+ --> file/path:1:9
+  |
+1 | let x = a + b + c;
+  |         ^ first
+  |             -   -
+  |
+  = note: 2 annotations simplified
+          second
+          third
+"#]];
+    let renderer = Renderer::plain().max_annotations_per_line(1);
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn header_points_at_first_annotation() {
+    let source = "let x = 1;\nlet y: u32 = \"2\";\nlet z: u32 = \"3\";";
+    let annotations = [
+        Level::Error.span(42..45).label("expected `u32`"),
+        Level::Info.span(4..5).label("declared here"),
+        Level::Error.span(24..27).label("expected `u32`"),
+    ];
+    let message = |annotations: Vec<_>| {
+        Level::Error.title("mismatched types").snippet(
+            Snippet::source(source)
+                .origin("src/main.rs")
+                .annotations(annotations),
+        )
+    };
+    let expected = str![[r#"
+error: mismatched types
+ --> src/main.rs:3:14
+  |
+1 | let x = 1;
+  |     - info: declared here
+2 | let y: u32 = "2";
+  |              ^^^ expected `u32`
+3 | let z: u32 = "3";
+  |              ^^^ expected `u32`
+  |
+"#]];
+    let renderer = Renderer::plain();
+    let rendered = renderer.render(message(annotations.to_vec())).to_string();
+    assert_data_eq!(rendered.clone(), expected);
+    // Only the first annotation picks the header, the order of the rest
+    // doesn't change the output
+    let mut reordered = annotations.to_vec();
+    reordered[1..].reverse();
+    assert_eq!(renderer.render(message(reordered)).to_string(), rendered);
+}
+
+#[test]
+fn max_annotations_per_line_keeps_primary() {
+    let source = "let x = a + b + c;";
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source(source)
+            .origin("file/path")
+            .annotation(Level::Warning.span(8..9).label("first"))
+            .annotation(Level::Warning.span(12..13).label("second"))
+            .annotation(Level::Error.span(16..17).label("third")),
+    );
+    let expected = str![[r#"
+error: oops
+ --> file/path:1:9
+  |
+1 | let x = a + b + c;
+  |                 ^ third
+  |         -   -
+  |
+  = note: 2 annotations simplified
+          first
+          second
+"#]];
+    let renderer = Renderer::plain().max_annotations_per_line(1);
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn max_annotations_per_line_many_primary() {
+    let source = "let x = aaaa + bbbb;";
+    let mut snippet = Snippet::source(source).origin("file/path");
+    for end in 9..=19 {
+        snippet = snippet.annotation(Level::Error.span(8..end).label("overlap"));
+    }
+    let input = Level::Error.title("oops").snippet(snippet);
+    let rendered = Renderer::plain()
+        .max_annotations_per_line(2)
+        .render(input)
+        .to_string();
+    let expected = str![[r#"
+error: oops
+ --> file/path:1:9
+  |
+1 | let x = aaaa + bbbb;
+  |         ^ overlap
+  |         ^^ overlap
+  |         ^^^^^^^^^^^
+  |
+  = note: 9 annotations simplified
+          overlap
+          overlap
+          overlap
+          overlap
+          overlap
+          overlap
+          overlap
+          overlap
+          overlap
+"#]];
+    assert_data_eq!(rendered, expected);
+}
+
+#[test]
+fn max_annotations_per_line_keeps_multiline() {
+    let source = "f(a, b, {\n    c\n});\n";
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source(source)
+            .origin("file/path")
+            .annotation(Level::Error.span(2..3).label("first"))
+            .annotation(Level::Warning.span(5..6).label("second"))
+            .annotation(Level::Warning.span(8..17).label("block")),
+    );
+    let expected = str![[r#"
+error: oops
+ --> file/path:1:3
+  |
+1 |   f(a, b, {
+  |     ^ first
+  |  _________-
+  |        -
+2 | |     c
+3 | | });
+  | |_- block
+  |
+  = note: 1 annotation simplified
+          second
+"#]];
+    let renderer = Renderer::plain().max_annotations_per_line(1);
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn render_with_layout_reports_truncation() {
+    let source = "                                                                                                                                                                                    let _: () = 42;";
+    let input = Level::Error.title("mismatched types").snippet(
+        Snippet::source(source)
+            .line_start(4)
+            .origin("$DIR/whitespace-trimming.rs")
+            .annotation(
+                Level::Error
+                    .span(192..194)
+                    .label("expected (), found integer"),
+            ),
+    );
+    let expected = str![[r#"
+error: mismatched types
+ --> $DIR/whitespace-trimming.rs:4:193
+  |
+4 | ...                   let _: () = 42;
+  |                                   ^^ expected (), found integer
+  |
+"#]];
+    let (rendered, layout) = Renderer::plain().render_with_layout(input);
+    assert_data_eq!(rendered, expected);
+    assert!(layout.is_truncated());
+    assert_eq!(layout.truncated_lines(), &[4]);
+
+    let input = Level::Error.title("mismatched types").snippet(
+        Snippet::source("let _: () = 42;")
+            .line_start(4)
+            .origin("$DIR/whitespace-trimming.rs")
+            .annotation(
+                Level::Error
+                    .span(12..14)
+                    .label("expected (), found integer"),
+            ),
+    );
+    let (_, layout) = Renderer::plain().render_with_layout(input);
+    assert!(!layout.is_truncated());
+}
+
+#[test]
+fn render_with_stats_ignores_escapes() {
+    let source = r#"let x: u32 = "42";"#;
+    let input = Level::Error.title("mismatched types").snippet(
+        Snippet::source(source).origin("src/main.rs").annotation(
+            Level::Error
+                .span(13..17)
+                .label("expected `u32`, found `&str`"),
+        ),
+    );
+    let (plain, plain_stats) = Renderer::plain().render_with_stats(input.clone());
+    assert_eq!(plain_stats.line_count(), 6);
+    assert_eq!(plain_stats.max_width(), 50);
+    assert_eq!(plain_stats.line_count(), plain.lines().count());
+
+    let (_, styled_stats) = Renderer::styled().render_with_stats(input);
+    assert_eq!(styled_stats, plain_stats);
+}
+
+#[test]
+fn wrap_labels() {
+    let source = r#"let x: u32 = "42";"#;
+    let input = Level::Error.title("mismatched types").snippet(
+        Snippet::source(source)
+            .origin("src/main.rs")
+            .annotation(
+                Level::Error
+                    .span(13..17)
+                    .label("expected `u32` because of the type annotation on the binding"),
+            )
+            .annotation(
+                Level::Warning
+                    .span(4..5)
+                    .label("this binding is declared here and never used again afterwards"),
+            ),
+    );
+    let expected = str![[r#"
+error: mismatched types
+ --> src/main.rs:1:14
+  |
+1 | let x: u32 = "42";
+  |              ^^^^ expected `u32`
+  |                   because of the
+  |                   type annotation on
+  |                   the binding
+  |     - this binding is declared here
+  |       and never used again
+  |       afterwards
+  |
+"#]];
+    let renderer = Renderer::plain().term_width(40).wrap_labels(true);
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn render_markdown() {
+    let input = Level::Error
+        .title("unresolved import `foo::bar`")
+        .id("E0432")
+        .id_url("https://doc.rust-lang.org/error_codes/E0432.html")
+        .snippet(
+            Snippet::source("use foo::bar;")
+                .origin("src/main.rs")
+                .annotation(Level::Error.span(4..7).label("no `foo` in the root")),
+        )
+        .footer(Level::Help.title("consider importing `bar`"));
+    let expected = str![[r#"
+**error[[E0432](https://doc.rust-lang.org/error_codes/E0432.html)]: unresolved import \`foo::bar\`**
+
+```text
+ --> src/main.rs:1:5
+  |
+1 | use foo::bar;
+  |     ^^^ no `foo` in the root
+  |
+  = help: consider importing `bar`
+```
+"#]];
+    let renderer = Renderer::styled().link_level(true);
+    assert_data_eq!(renderer.render_markdown(input), expected);
+}
+
+#[test]
+fn render_markdown_escapes_title() {
+    let input = Level::Warning
+        .title("unused `x` in *main*\n- remove it\n\n[x]\tis never read")
+        .snippet(Snippet::source("let x = 1;").annotation(Level::Warning.span(4..5)));
+    let expected = str![[r#"
+**warning: unused \`x\` in \*main\*\
+\- remove it\
+\
+\[x\]    is never read**
+
+```text
+  |
+1 | let x = 1;
+  |     -
+  |
+```
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render_markdown(input), expected);
+}
+
+#[test]
+fn span_inclusive_to_end_of_source() {
+    let source = "let x = 1;";
+    let input = Level::Error.title("").snippet(
+        Snippet::source(source)
+            .origin("file/path")
+            .annotation(Level::Error.span_inclusive(8..=9).label("label")),
+    );
+    let expected = str![[r#"
+error
+ --> file/path:1:9
+  |
+1 | let x = 1;
+  |         ^^ label
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[cfg(feature = "lsp")]
+#[test]
+fn lsp_diagnostics() {
+    use annotate_snippets::lsp::{self, Position, Severity};
+
+    let source = "fn main() {\n    let é: u32 = \"42\";\n}";
+    let input = Level::Error
+        .title("mismatched types")
+        .id("E0308")
+        .snippet(
+            Snippet::source(source)
+                .origin("src/main.rs")
+                .line_start(3)
+                .annotation(
+                    Level::Error
+                        .span(30..34)
+                        .label("expected `u32`, found `&str`"),
+                )
+                .annotation(Level::Info.span(24..27).label("expected due to this")),
+        )
+        .footer(Level::Help.title("try parsing it"));
+    let range = |line, start, end| lsp::Range {
+        start: Position {
+            line,
+            character: start,
+        },
+        end: Position {
+            line,
+            character: end,
+        },
+    };
+
+    let diagnostics = lsp::to_diagnostics(&input);
+    assert_eq!(diagnostics.len(), 1);
+    let diagnostic = &diagnostics[0];
+    assert_eq!(diagnostic.origin.as_deref(), Some("src/main.rs"));
+    assert_eq!(diagnostic.range, range(3, 17, 21));
+    assert_eq!(diagnostic.severity, Some(Severity::Error));
+    assert_eq!(diagnostic.code.as_deref(), Some("E0308"));
+    assert_eq!(
+        diagnostic.message,
+        "mismatched types\nexpected `u32`, found `&str`\nhelp: try parsing it"
+    );
+    assert_eq!(diagnostic.related_information.len(), 1);
+    let related = &diagnostic.related_information[0];
+    assert_eq!(related.range, range(3, 11, 14));
+    assert_eq!(related.message, "expected due to this");
+}
+
+#[test]
+fn message_getters() {
+    let source = "let x: u32 = \"42\";";
+    let input = Level::Error
+        .title("mismatched types")
+        .id("E0308")
+        .snippet(
+            Snippet::source(source)
+                .line_start(7)
+                .origin("src/main.rs")
+                .annotation(Level::Error.span(13..17).label("expected `u32`"))
+                .annotation(Level::Info.span(7..10)),
+        )
+        .footer(Level::Help.title("try parsing it"));
+
+    assert_eq!(input.get_level(), Level::Error);
+    assert_eq!(input.get_title(), "mismatched types");
+    assert_eq!(input.get_id(), Some("E0308"));
+    assert_eq!(input.get_footers()[0].get_title(), "try parsing it");
+
+    let snippet = &input.get_snippets()[0];
+    assert_eq!(snippet.get_source(), source);
+    assert_eq!(snippet.get_line_start(), 7);
+    assert_eq!(snippet.get_origin(), Some("src/main.rs"));
+
+    let annotations = snippet.get_annotations();
+    assert_eq!(annotations.len(), 2);
+    assert_eq!(annotations[0].get_level(), Level::Error);
+    assert_eq!(annotations[0].get_range(), 13..17);
+    assert_eq!(annotations[0].get_label(), Some("expected `u32`"));
+    assert_eq!(annotations[1].get_label(), None);
+}
+
+#[test]
+fn anonymized_line_num_str() {
+    let source = "fn main() {\n    let x: u32 = \"42\";\n}";
+    let input = Level::Error.title("mismatched types").snippet(
+        Snippet::source(source)
+            .line_start(7)
+            .origin("src/main.rs")
+            .annotation(
+                Level::Error
+                    .span(29..33)
+                    .label("expected `u32`, found `&str`"),
+            ),
+    );
+    let expected = str![[r#"
+error: mismatched types
+    --> src/main.rs:8:18
+     |
+LINE | fn main() {
+LINE |     let x: u32 = "42";
+     |                  ^^^^ expected `u32`, found `&str`
+LINE | }
+     |
+"#]];
+    let renderer = Renderer::plain()
+        .anonymized_line_numbers(true)
+        .anonymized_line_num_str("LINE");
+    assert_data_eq!(renderer.render(input.clone()).to_string(), expected);
+
+    let expected = str![[r#"
+error: mismatched types
+    --> src/main.rs:8:18
+     |
+行号 | fn main() {
+行号 |     let x: u32 = "42";
+     |                  ^^^^ expected `u32`, found `&str`
+行号 | }
+     |
+"#]];
+    let renderer = Renderer::plain()
+        .anonymized_line_numbers(true)
+        .anonymized_line_num_str("行号");
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn renderer_from_env() {
+    let input = || {
+        Level::Error
+            .title("oops")
+            .snippet(Snippet::source("let x = 42;").annotation(Level::Error.span(8..10)))
+    };
+    let plain = Renderer::plain().render(input()).to_string();
+    let styled = Renderer::styled().render(input()).to_string();
+
+    // Only this test reads these variables
+    std::env::set_var("NO_COLOR", "1");
+    std::env::set_var("CLICOLOR_FORCE", "1");
+    assert_eq!(Renderer::from_env().render(input()).to_string(), plain);
+
+    std::env::remove_var("NO_COLOR");
+    assert_eq!(Renderer::from_env().render(input()).to_string(), styled);
+
+    std::env::set_var("CLICOLOR_FORCE", "0");
+    std::env::set_var("CLICOLOR", "0");
+    assert_eq!(Renderer::from_env().render(input()).to_string(), plain);
+
+    std::env::remove_var("CLICOLOR_FORCE");
+    std::env::remove_var("CLICOLOR");
+}
+
+#[test]
+fn max_unfolded_lines() {
+    let source = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj";
+    let input = || {
+        Level::Error.title("").snippet(
+            Snippet::source(source)
+                .fold(true)
+                .annotation(Level::Error.span(0..1).label("first"))
+                .annotation(Level::Error.span(18..19).label("last")),
+        )
+    };
+    let expected = str![[r#"
+error
+   |
+ 1 | a
+   | ^ first
+ 2 | b
+...
+ 9 | i
+10 | j
+   | ^ last
+   |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input()).to_string(), expected);
+
+    let expected = str![[r#"
+error
+   |
+ 1 | a
+   | ^ first
+ 2 | b
+ 3 | c
+ 4 | d
+ 5 | e
+ 6 | f
+ 7 | g
+ 8 | h
+ 9 | i
+10 | j
+   | ^ last
+   |
+"#]];
+    let renderer = Renderer::plain().max_unfolded_lines(8);
+    assert_data_eq!(renderer.render(input()).to_string(), expected);
+}
+
+#[test]
+fn dedup_messages() {
+    let message = |label| {
+        Level::Error.title("mismatched types").snippet(
+            Snippet::source("let x: u32 = \"42\";")
+                .origin("src/main.rs")
+                .annotation(Level::Error.span(13..17).label(label)),
+        )
+    };
+    let messages: std::collections::HashSet<_> = [
+        message("expected `u32`"),
+        message("expected `u32`"),
+        message("found `&str`"),
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(messages.len(), 2);
+    assert!(messages.contains(&message("found `&str`")));
+}
+
+#[test]
+fn try_render() {
+    let input = || {
+        Level::Error
+            .title("oops")
+            .snippet(Snippet::source("let x = 42;").annotation(Level::Error.span(8..10)))
+    };
+    let renderer = Renderer::plain();
+    assert_eq!(
+        renderer.try_render(input()),
+        Ok(renderer.render(input()).to_string())
+    );
+}
+
+#[test]
+fn assume_ltr() {
+    let source = r#"let s = "שלום";"#;
+    let input = || {
+        Level::Error
+            .title("oops")
+            .snippet(Snippet::source(source).annotation(Level::Error.span(9..17)))
+    };
+    let expected = str![[r#"
+error: oops
+  |
+1 | let s = "שלום";
+  |          ^^^^
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input()).to_string(), expected);
+
+    let renderer = renderer.assume_ltr(false);
+    let rendered = renderer.render(input()).to_string();
+    assert_eq!(
+        rendered.lines().nth(2),
+        Some("1 | \u{202D}let s = \"שלום\";\u{202C}")
+    );
+}
+
+#[test]
+fn underline_glyphs() {
+    let source = r#"fn foo() {
+    bar();
+}"#;
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source(source)
+            .annotation(Level::Error.span(3..6).label("primary"))
+            .annotation(Level::Warning.span(9..23).label("multiline")),
+    );
+    let expected = str![[r#"
+error: oops
+  |
+1 |   fn foo() {
+  |      ~~~ primary
+  |  ==========.
+2 | !     bar();
+3 | ! }
+  | !=. multiline
+  |
+"#]];
+    let glyphs = UnderlineGlyphs::new()
+        .primary('~')
+        .secondary('.')
+        .multiline_horizontal('=')
+        .multiline_vertical('!');
+    let renderer = Renderer::plain().underline_glyphs(glyphs);
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn shared_underline() {
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source("let x = foo(1);")
+            .annotation(Level::Error.span(8..11).label("not a function"))
+            .annotation(Level::Error.span(8..11).label("not in scope"))
+            .annotation(Level::Warning.span(12..13).label("argument")),
+    );
+    let expected = str![[r#"
+error: oops
+  |
+1 | let x = foo(1);
+  |         ^^^ not a function
+  |         |   not in scope
+  |             - argument
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn locate() {
+    let source = "fn main() {\n    let é = 1;\n}";
+    let snippet = Snippet::source(source).line_start(5);
+    let start = source.find('1').unwrap();
+    assert_eq!(
+        snippet.locate(start..source.len()),
+        (
+            Location {
+                line: 6,
+                column: 13
+            },
+            Location { line: 7, column: 2 }
+        )
+    );
+
+    // Agrees with the `-->` header
+    let input = Level::Error.title("oops").snippet(
+        snippet
+            .origin("src/main.rs")
+            .annotation(Level::Error.span(start..start + 1)),
+    );
+    let rendered = Renderer::plain().render(input).to_string();
+    assert_eq!(rendered.lines().nth(1), Some(" --> src/main.rs:6:13"));
+}
+
+#[test]
+fn separators() {
+    let input = || {
+        Level::Warning
+            .title("function `foo` is never used")
+            .snippet(
+                Snippet::source("fn foo() {}")
+                    .origin("src/main.rs")
+                    .annotation(Level::Warning.span(3..6).label("not used")),
+            )
+            .snippet(
+                Snippet::source("fn bar() {}")
+                    .line_start(2)
+                    .origin("src/lib.rs")
+                    .annotation(Level::Info.span(0..11).label("defined here")),
+            )
+            .footer(Level::Note.title("`#[warn(dead_code)]` on by default"))
+    };
+    let expected = str![[r#"
+warning: function `foo` is never used
+ ╭▸ src/main.rs:1:4
+  :
+1 : fn foo() {}
+  :    --- not used
+  :
+ ... src/lib.rs:2:1
+  :
+2 : fn bar() {}
+  : ----------- info: defined here
+  :
+  = note: `#[warn(dead_code)]` on by default
+"#]];
+    // `全` is two columns wide, so `=` is kept
+    let separators = Separators::new()
+        .col_separator(':')
+        .file_start("╭▸")
+        .secondary_file_start("...")
+        .note_separator('全');
+    let renderer = Renderer::plain().separators(separators);
+    assert_data_eq!(renderer.render(input()).to_string(), expected);
+}
+
+#[test]
+fn source_window() {
+    let file = "fn main() {}\nfn foo() {\n    bar();\n}\nfn baz() {}\n";
+    let start = file.find("fn foo").unwrap();
+    let end = file.find("fn baz").unwrap();
+    let bar = file.find("bar").unwrap();
+    let input = Level::Error.title("cannot find function `bar`").snippet(
+        Snippet::source_window(&file[start..end], start)
+            .line_start(2)
+            .origin("src/main.rs")
+            .annotation(Level::Error.span(bar..bar + 3).label("not found"))
+            // Before the window
+            .annotation(Level::Info.span(3..7).label("main"))
+            // After the window
+            .annotation(Level::Info.span(end + 3..end + 6).label("baz")),
+    );
+    let expected = str![[r#"
+error: cannot find function `bar`
+ --> src/main.rs:3:5
+  |
+2 | fn foo() {
+3 |     bar();
+  |     ^^^ not found
+4 | }
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn trailing_newline() {
+    let renderer = Renderer::plain().trailing_newline(true);
+    let input = Level::Error.title("oops");
+    assert_eq!(renderer.render(input).to_string(), "error: oops\n");
+
+    // Not doubled up
+    let input = Level::Error
+        .title("oops")
+        .footer(Level::Note.title("see\n"));
+    assert_eq!(
+        renderer.render(input).to_string(),
+        "error: oops\n = note: see\n"
+    );
+
+    let input = Level::Error.title("oops");
+    let renderer = renderer.trailing_newline(false);
+    assert_eq!(renderer.render(input).to_string(), "error: oops");
+}
+
+#[test]
+fn secondary_line_no() {
+    let input = Level::Error
+        .title("oops")
+        .snippet(
+            Snippet::source("fn foo() {}")
+                .origin("src/main.rs")
+                .annotation(Level::Error.span(3..6)),
+        )
+        .snippet(
+            Snippet::source("fn bar() {}")
+                .origin("src/lib.rs")
+                .line_start(2)
+                .annotation(Level::Info.span(3..6)),
+        )
+        .snippet(
+            Snippet::source("fn baz() {}")
+                .origin("src/main.rs")
+                .line_start(3)
+                .annotation(Level::Info.span(3..6)),
+        );
+    let renderer = Renderer::plain().secondary_line_no(AnsiColor::BrightBlack.on_default());
+    let rendered = renderer.render(input).to_string();
+    let expected = [
+        "error: oops",
+        " --> src/main.rs:1:4",
+        "  |",
+        "1 | fn foo() {}",
+        "  |    ^^^",
+        "  |",
+        " \u{1b}[90m:::\u{1b}[0m src/lib.rs:2:4",
+        "\u{1b}[90m  |\u{1b}[0m",
+        "\u{1b}[90m2 |\u{1b}[0m fn bar() {}",
+        "\u{1b}[90m  |\u{1b}[0m    ---",
+        "\u{1b}[90m  |\u{1b}[0m",
+        " ::: src/main.rs:3:4",
+        "  |",
+        "3 | fn baz() {}",
+        "  |    ---",
+        "  |",
+    ];
+    assert_eq!(rendered.lines().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn char_width() {
+    let source = "let é = 1;";
+    let input = Level::Error
+        .title("oops")
+        .snippet(Snippet::source(source).annotation(Level::Error.span(9..10)));
+    let expected = str![[r#"
+error: oops
+  |
+1 | let é = 1;
+  |          ^
+  |
+"#]];
+    // A font that draws every non-ASCII char two columns wide
+    let renderer = Renderer::plain().char_width(|ch| if ch.is_ascii() { 1 } else { 2 });
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+
+    let label = MultiLineLabel::new().row(["é:", "1"]).row(["ab:", "2"]);
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source(source).annotation(Level::Error.span(9..10).multi_line_label(label)),
+    );
+    let expected = str![[r#"
+error: oops
+  |
+1 | let é = 1;
+  |          ^
+  |          é: 1
+  |          ab: 2
+  |
+"#]];
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn label_line_breaks() {
+    let source = r#"fn foo() {
+    bar(1);
+}"#;
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source(source)
+            .annotation(Level::Error.span(15..18).label("not found\nin this scope"))
+            .annotation(Level::Warning.span(9..24).label("in this\nfunction")),
+    );
+    let expected = str![[r#"
+error: oops
+  |
+1 |   fn foo() {
+  |  __________-
+2 | |     bar(1);
+  | |     ^^^ not found
+  | |         in this scope
+3 | | }
+  | |_- in this
+  | |   function
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn custom_level() {
+    let security = Level::custom("security", AnsiColor::Magenta.on_default());
+    let input = security
+        .title("password is stored in plain text")
+        .id("S001")
+        .snippet(
+            Snippet::source("let password = \"hunter2\";")
+                .annotation(security.span(15..24).label("hardcoded here")),
+        );
+
+    let expected = str![[r#"
+security[S001]: password is stored in plain text
+  |
+1 | let password = "hunter2";
+  |                --------- hardcoded here
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input.clone()).to_string(), expected);
+
+    let rendered = Renderer::styled().render(input).to_string();
+    assert!(rendered.starts_with("\u{1b}[35msecurity[S001]\u{1b}[0m: "));
+    assert!(rendered.contains("\u{1b}[35m                ---------\u{1b}[0m"));
+}
+
+#[test]
+fn count_folded_lines() {
+    let source = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj";
+    let input = || {
+        Level::Error.title("").snippet(
+            Snippet::source(source)
+                .fold(true)
+                .annotation(Level::Error.span(0..1).label("first"))
+                .annotation(Level::Error.span(18..19).label("last")),
+        )
+    };
+    let expected = str![[r#"
+error
+   |
+ 1 | a
+   | ^ first
+ 2 | b
+... (6 lines)
+ 9 | i
+10 | j
+   | ^ last
+   |
+"#]];
+    let renderer = Renderer::plain().count_folded_lines(true);
+    assert_data_eq!(renderer.render(input()).to_string(), expected);
+
+    let expected = str![[r#"
+error
+   |
+LL | a
+   | ^ first
+LL | b
+...
+LL | i
+LL | j
+   | ^ last
+   |
+"#]];
+    let renderer = renderer.anonymized_line_numbers(true);
+    assert_data_eq!(renderer.render(input()).to_string(), expected);
+}
+
+#[test]
+fn count_folded_lines_anonymized_snippet() {
+    let source = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj";
+    let input = |anonymized| {
+        Level::Error.title("").snippet(
+            Snippet::source(source)
+                .fold(true)
+                .anonymized(anonymized)
+                .annotation(Level::Error.span(0..1).label("first"))
+                .annotation(Level::Error.span(18..19).label("last")),
+        )
+    };
+    let expected = str![[r#"
+error
+   |
+LL | a
+   | ^ first
+LL | b
+...
+LL | i
+LL | j
+   | ^ last
+   |
+"#]];
+    let renderer = Renderer::plain().count_folded_lines(true);
+    assert_data_eq!(renderer.render(input(true)).to_string(), expected);
+
+    let expected = str![[r#"
+error
+   |
+ 1 | a
+   | ^ first
+ 2 | b
+... (6 lines)
+ 9 | i
+10 | j
+   | ^ last
+   |
+"#]];
+    let renderer = renderer.anonymized_line_numbers(true);
+    assert_data_eq!(renderer.render(input(false)).to_string(), expected);
+}
+
+#[test]
+fn stylesheet() {
+    const THEME: Stylesheet = Stylesheet::plain()
+        .error(AnsiColor::Red.on_default())
+        .line_no(AnsiColor::BrightBlack.on_default());
+    let input = || {
+        Level::Error.title("oops").snippet(
+            Snippet::source("let x = 42;").annotation(Level::Error.span(8..10).label("here")),
+        )
+    };
+
+    let renderer = Renderer::plain().stylesheet(THEME);
+    let expected = "\u{1b}[31merror\u{1b}[0m: oops
+\u{1b}[90m  |\u{1b}[0m
+\u{1b}[90m1 |\u{1b}[0m let x = 42;
+\u{1b}[90m  |\u{1b}[0m\u{1b}[31m         ^^\u{1b}[0m \u{1b}[31mhere\u{1b}[0m
+\u{1b}[90m  |\u{1b}[0m";
+    assert_eq!(renderer.render(input()).to_string(), expected);
+
+    let renderer = Renderer::plain()
+        .error(AnsiColor::Red.on_default())
+        .line_no(AnsiColor::BrightBlack.on_default());
+    assert_eq!(renderer.render(input()).to_string(), expected);
+
+    let renderer = Renderer::styled().stylesheet(Stylesheet::plain());
+    let expected = Renderer::plain().render(input()).to_string();
+    assert_eq!(renderer.render(input()).to_string(), expected);
+}
+
+#[test]
+fn cut_markers() {
+    let source =
+        "                                        let y = 42; // a comment that doesn't fit";
+    let input = Level::Error
+        .title("oops")
+        .snippet(Snippet::source(source).annotation(Level::Error.span(44..45)));
+
+    let expected = str![[r#"
+error: oops
+  |
+1 | ...   let y = 42; // a comment th...
+  |           ^
+  |
+"#]];
+    let renderer = Renderer::plain().term_width(40);
+    assert_data_eq!(renderer.render(input.clone()).to_string(), expected);
+
+    let expected = str![[r#"
+error: oops
+  |
+1 | ‹     let y = 42; // a comment that›
+  |           ^
+  |
+"#]];
+    let renderer = renderer.cut_markers("‹", "›");
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn cut_right_multibyte() {
+    let source = "let x = 42; // éééééééééééééééééééééééééééééé";
+    let input = Level::Error
+        .title("oops")
+        .snippet(Snippet::source(source).annotation(Level::Error.span(4..5)));
+    let expected = str![[r#"
+error: oops
+  |
+1 | let x = 42; // ééééééééé...
+  |     ^
+  |
+"#]];
+    let renderer = Renderer::plain().term_width(31);
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn multiline_whole_line_mark() {
+    let source = "fn foo() {\n    bar();\n}";
+    let input = Level::Error.title("").snippet(
+        Snippet::source(source)
+            .origin("file/path")
+            .line_start(2)
+            .annotation(Level::Error.span(0..23).label("label")),
+    );
+    let expected = str![[r#"
+error
+ --> file/path:2:1
+  |
+2 | + fn foo() {
+3 | |     bar();
+4 | | }
+  | |_^ label
+  |
+"#]];
+    let renderer = Renderer::plain().multiline_whole_line('+');
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn message_level_constructors() {
+    let input = Message::warning("unused variable").id("W0001");
+
+    let expected = str![r#"warning[W0001]: unused variable"#];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn highlight_style_background() {
+    let source = "let x = 42;";
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source(source)
+            .origin("file/path")
+            .annotation(Level::Error.span(8..10).label("here"))
+            .annotation(Level::Warning.span(4..5)),
+    );
+    let renderer = Renderer::plain()
+        .error(AnsiColor::Red.on_default())
+        .highlight_style(HighlightStyle::Background);
+    let expected = "\u{1b}[31merror\u{1b}[0m: oops
+ --> file/path:1:9
+  |
+1 | let x = \u{1b}[41m42\u{1b}[0m;
+  |\u{1b}[31m           \u{1b}[0m \u{1b}[31mhere\u{1b}[0m
+  |     -
+  |";
+    assert_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn annotate_matches() {
+    let source = "let aaa = foo(aa, foo);";
+    let input = Level::Error.title("").snippet(
+        Snippet::source(source)
+            .origin("file/path")
+            .annotate_matches("foo", Level::Error, "found foo")
+            .annotate_matches("aa", Level::Warning, "found aa")
+            .annotate_matches("", Level::Warning, "found nothing"),
+    );
+    let expected = str![[r#"
+error
+ --> file/path:1:11
+  |
+1 | let aaa = foo(aa, foo);
+  |           ^^^ found foo
+  |                   ^^^ found foo
+  |     -- found aa
+  |               -- found aa
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn indent() {
+    let source = "                                                                                                                                                                                    let _: () = 42;";
+    let input = Level::Error
+        .title("mismatched types")
+        .snippet(
+            Snippet::source(source)
+                .line_start(4)
+                .origin("$DIR/whitespace-trimming.rs")
+                .annotation(
+                    Level::Error
+                        .span(192..194)
+                        .label("expected (), found integer"),
+                ),
+        )
+        .footer(Level::Note.title("some note"));
+    let expected = str![[r#"
+    error: mismatched types
+     --> $DIR/whitespace-trimming.rs:4:193
+      |
+    4 | ... _: () = 42;
+      |             ^^ expected (), found integer
+      |
+      = note: some note
+"#]];
+    let renderer = Renderer::plain().indent(4).term_width(40);
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn fold_keeps_every_annotated_line() {
+    // Folding only elides lines without annotations, so context annotations
+    // far from the primary one are never folded away.
+    let source = "fn main() {\n    let a = 1;\n    let b = 2;\n    let c = 3;\n    let d = 4;\n    let e = 5;\n    let f = 6;\n    let g = 7;\n    let h = 8;\n    let i = 9;\n    let j = 10;\n    let k = 11;\n    let l = 12;\n    a + l\n}";
+    let input = Level::Error.title("mismatched types").snippet(
+        Snippet::source(source)
+            .origin("src/main.rs")
+            .fold(true)
+            .annotation(Level::Info.span(20..21).label("root cause"))
+            .annotation(Level::Info.span(95..96).label("unrelated context"))
+            .annotation(Level::Error.span(199..204).label("expected `()`")),
+    );
+    let expected = str![[r#"
+error: mismatched types
+  --> src/main.rs:2:9
+   |
+ 2 |     let a = 1;
+   |         - info: root cause
+ 3 |     let b = 2;
+...
+ 6 |     let e = 5;
+ 7 |     let f = 6;
+   |         - info: unrelated context
+ 8 |     let g = 7;
+...
+13 |     let l = 12;
+14 |     a + l
+   |     ^^^^^ expected `()`
+   |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn label_tabs() {
+    let input = Level::Error.title("").snippet(
+        Snippet::source("let x = 42;")
+            .origin("file/path")
+            .annotation(Level::Error.span(8..10).label("expected\tfound")),
+    );
+    let expected = str![[r#"
+error
+ --> file/path:1:9
+  |
+1 | let x = 42;
+  |         ^^ expected	found
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+
+    let input = Level::Error.title("").snippet(
+        Snippet::source("let x = 42;")
+            .origin("file/path")
+            .annotation(Level::Error.span(8..10).label("expected\tfound")),
+    );
+    let expected = str![[r#"
+error
+ --> file/path:1:9
+  |
+1 | let x = 42;
+  |         ^^ expected    found
+  |
+"#]];
+    let renderer = Renderer::plain().normalize_labels(true);
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn eof_after_trailing_newline() {
+    let source = "fn main() {\n    foo(\n";
+    let input = Level::Error.title("unexpected end of file").snippet(
+        Snippet::source(source).origin("src/main.rs").annotation(
+            Level::Error
+                .span(source.len()..source.len())
+                .label("expected `)`"),
+        ),
+    );
+    let expected = str![[r#"
+error: unexpected end of file
+ --> src/main.rs:3:1
+  |
+1 | fn main() {
+2 |     foo(
+3 | 
+  | ^ expected `)`
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn eof_without_trailing_newline() {
+    let source = "fn main() {\n    foo()";
+    let input = Level::Error.title("unclosed delimiter").snippet(
+        Snippet::source(source)
+            .origin("src/main.rs")
+            .annotation(
+                Level::Error
+                    .span(source.len()..source.len())
+                    .label("expected `}`"),
+            )
+            .annotation(Level::Info.span(10..11).label("unclosed delimiter")),
+    );
+    let expected = str![[r#"
+error: unclosed delimiter
+ --> src/main.rs:2:10
+  |
+1 | fn main() {
+  |           - info: unclosed delimiter
+2 |     foo()
+  |          ^ expected `}`
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn fold_eof_after_trailing_newline() {
+    let source = "fn main() {\n    foo(\n";
+    let input = Level::Error.title("unexpected end of file").snippet(
+        Snippet::source(source)
+            .origin("src/main.rs")
+            .fold(true)
+            .annotation(
+                Level::Error
+                    .span(source.len()..source.len())
+                    .label("expected `)`"),
+            ),
+    );
+    let expected = str![[r#"
+error: unexpected end of file
+ --> src/main.rs:3:1
+  |
+3 | 
+  | ^ expected `)`
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn origin_at_start_of_line() {
+    let input = Level::Error.title("").snippet(
+        Snippet::source("abc\ndef")
+            .origin("file/path")
+            .annotation(Level::Error.span(4..5)),
+    );
+    let expected = str![[r#"
+error
+ --> file/path:2:1
+  |
+1 | abc
+2 | def
+  | ^
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn anonymized_per_snippet() {
+    let input = Level::Error
+        .title("mismatched types")
+        .snippet(
+            Snippet::source("let x: u8 = foo();")
+                .origin("src/main.rs")
+                .line_start(120)
+                .annotation(Level::Error.span(12..17).label("expected `u8`")),
+        )
+        .snippet(
+            Snippet::source("fn foo() -> u16 {")
+                .origin("$SRC_DIR/lib.rs")
+                .line_start(4)
+                .anonymized(true)
+                .annotation(Level::Info.span(12..15).label("defined here")),
+        );
+    let expected = str![[r#"
+error: mismatched types
+   --> src/main.rs:120:13
+    |
+120 | let x: u8 = foo();
+    |             ^^^^^ expected `u8`
+    |
+   ::: $SRC_DIR/lib.rs:4:13
+    |
+ LL | fn foo() -> u16 {
+    |             --- info: defined here
+    |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn dedup_annotations() {
+    let input = || {
+        Level::Error.title("").snippet(
+            Snippet::source("let x = 42;")
+                .origin("file/path")
+                .annotation(Level::Error.span(8..10).label("here"))
+                .annotation(Level::Error.span(8..10).label("here"))
+                .annotation(Level::Warning.span(8..10).label("here")),
+        )
+    };
+    let expected = str![[r#"
+error
+ --> file/path:1:9
+  |
+1 | let x = 42;
+  |         ^^ here
+  |         -- here
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input()).to_string(), expected);
+
+    let expected = str![[r#"
+error
+ --> file/path:1:9
+  |
+1 | let x = 42;
+  |         ^^ here
+  |         ^^ here
+  |         -- here
+  |
+"#]];
+    let renderer = Renderer::plain().dedup_annotations(false);
+    assert_data_eq!(renderer.render(input()).to_string(), expected);
+}
+
+#[test]
+fn inline_underline() {
+    let input = Level::Error.title("").snippet(
+        Snippet::source("let x = 42;\nlet y = x +\n    1;")
+            .origin("file/path")
+            .annotation(Level::Error.span(8..10).label("here"))
+            .annotation(Level::Warning.span(4..5))
+            .annotation(Level::Warning.span(20..27).label("multiline")),
+    );
+    let expected = str![[r#"
+error
+ --> file/path:1:9
+  |
+1 |   let x̲ = 4̲2̲;
+  |              here
+2 |   let y = x +
+  |  _________-
+3 | |     1;
+  | |___- multiline
+  |
+"#]];
+    let renderer = Renderer::plain().inline_underline(true);
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn origin_from_path() {
+    let path = std::path::Path::new("src/main.rs");
+    let input = Level::Error
+        .title("")
+        .snippet(
+            Snippet::source("let x = 42;")
+                .origin_path(path)
+                .annotation(Level::Error.span(8..10)),
+        )
+        .snippet(
+            Snippet::source("let y = 42;")
+                .path_buf(path.with_file_name("lib.rs"))
+                .annotation(Level::Error.span(8..10)),
+        );
+    let expected = str![[r#"
+error
+ --> src/main.rs:1:9
+  |
+1 | let x = 42;
+  |         ^^
+  |
+ ::: src/lib.rs:1:9
+  |
+1 | let y = 42;
+  |         ^^
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn dual_line_numbers() {
+    let source = r#"fn foo() {
+    bar();
+}
+"#;
+    let input = Level::Error.title("cannot find function `bar`").snippet(
+        Snippet::source(source)
+            .line_start(500)
+            .origin("src/format.rs")
+            .annotation(Level::Error.span(15..18).label("not found in this scope")),
+    );
+    let expected = str![[r#"
+error: cannot find function `bar`
+     --> src/format.rs:501:5
+      |
+500 1 | fn foo() {
+501 2 |     bar();
+      |     ^^^ not found in this scope
+502 3 | }
+      |
+"#]];
+    let renderer = Renderer::plain().dual_line_numbers(true);
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn level_note_prefix() {
+    let input = Level::Error
+        .title("oops")
+        .snippet(Snippet::source("let x = 42;").annotation(Level::Error.span(8..10)))
+        .footer(Level::Help.title("try this"))
+        .footer(Level::Note.title("and this"));
+    let renderer = Renderer::plain()
+        .help(AnsiColor::Cyan.on_default())
+        .note(AnsiColor::Green.on_default())
+        .level_note_prefix(true);
+    let expected = "error: oops
+  |
+1 | let x = 42;
+  |         ^^
+  |
+  \u{1b}[36m=\u{1b}[0m \u{1b}[36mhelp\u{1b}[0m: try this
+  \u{1b}[32m=\u{1b}[0m \u{1b}[32mnote\u{1b}[0m: and this";
+    assert_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+#[allow(clippy::reversed_empty_ranges)]
+fn validate() {
+    let source = "let é = 42;";
+    let input = Level::Error
+        .title("")
+        .snippet(
+            Snippet::source(source)
+                .line_start(0)
+                .annotation(Level::Error.span(4..6))
+                .annotation(Level::Error.span(5..6))
+                .annotation(Level::Error.span(8..4))
+                .annotation(Level::Error.span(10..14)),
+        )
+        .footer(
+            Level::Note
+                .title("")
+                .snippet(Snippet::source(source).annotation(Level::Note.span(0..20))),
+        );
+    assert_eq!(
+        input.validate(),
+        Err(vec![
+            ValidationError::ZeroLineStart,
+            ValidationError::NotCharBoundary { range: 5..6 },
+            ValidationError::InvertedRange { range: 8..4 },
+            ValidationError::OutOfBounds {
+                range: 10..14,
+                source_len: 12
+            },
+            ValidationError::OutOfBounds {
+                range: 0..20,
+                source_len: 12
+            },
+        ])
+    );
+
+    let input = Level::Error
+        .title("")
+        .snippet(Snippet::source(source).annotation(Level::Error.span(4..12)));
+    assert_eq!(input.validate(), Ok(()));
+}
+
+#[test]
+fn validate_span_at_eof() {
+    let source = "let x = 42";
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source(source)
+            .origin("file/path")
+            .annotation(Level::Error.span(10..11).label("expected `;`")),
+    );
+    assert_eq!(input.validate(), Ok(()));
+    let expected = str![[r#"
+error: oops
+ --> file/path:1:11
+  |
+1 | let x = 42
+  |           ^ expected `;`
+  |
+"#]];
+    let renderer = Renderer::plain();
+    let rendered = renderer.render(input.clone()).to_string();
+    assert_data_eq!(rendered.clone(), expected);
+    assert_eq!(
+        renderer.render_checked(input).unwrap().to_string(),
+        rendered
+    );
+}
+
+#[test]
+fn out_of_bounds_spans_are_clamped() {
+    let source = "let x = 42;";
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source(source)
+            .origin("file/path")
+            .annotation(Level::Error.span(8..5000).label("clamped"))
+            .annotation(Level::Warning.span(25..30).label("skipped")),
+    );
+    let expected = str![[r#"
+error: oops
+ --> file/path:1:9
+  |
+1 | let x = 42;
+  |         ^^^ clamped
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input.clone()).to_string(), expected);
+
+    let error = renderer.render_checked(input).unwrap_err();
+    assert_eq!(error.annotation(), 0);
+    assert_eq!(
+        error.error(),
+        &ValidationError::OutOfBounds {
+            range: 8..5000,
+            source_len: 11
+        }
+    );
+
+    let input = Level::Error
+        .title("oops")
+        .snippet(Snippet::source(source).annotation(Level::Error.span(8..10)))
+        .footer(
+            Level::Note
+                .title("")
+                .snippet(Snippet::source(source).annotation(Level::Note.span(25..30))),
+        );
+    assert_eq!(renderer.render_checked(input).unwrap_err().annotation(), 1);
+}
+
+#[test]
+fn strict() {
+    let source = "fn a() {\n    b();\n    c();\n    d();\n}\n";
+    let input = |first, second| {
+        Level::Error.title("oops").snippet(
+            Snippet::source(source)
+                .annotation(Level::Error.span(first).label("first"))
+                .annotation(Level::Warning.span(second).label("second")),
+        )
+    };
+    let renderer = Renderer::plain().strict(true);
+
+    let error = renderer.render_checked(input(3..25, 14..38)).unwrap_err();
+    assert_eq!(error.annotation(), 1);
+    assert_eq!(
+        error.error(),
+        &ValidationError::InterleavedMultiline {
+            range: 14..38,
+            other: 3..25
+        }
+    );
+    assert!(Renderer::plain()
+        .render_checked(input(3..25, 14..38))
+        .is_ok());
+
+    // Nested and consecutive annotations can be drawn
+    assert!(renderer.render_checked(input(3..38, 14..25)).is_ok());
+    assert!(renderer.render_checked(input(3..14, 20..38)).is_ok());
+}
+
+#[test]
+fn focus() {
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source("let x = 42;")
+            .annotation(Level::Error.span(8..10).label("here"))
+            .annotation(Level::Warning.span(4..5).label("there")),
+    );
+    let renderer = Renderer::plain()
+        .error(AnsiColor::Red.on_default())
+        .warning(AnsiColor::Yellow.on_default())
+        .focus(Some(1));
+    let expected = "\u{1b}[31merror\u{1b}[0m: oops
+  |
+1 | let x = 42;
+  |         ^^ here
+  |\u{1b}[33m     -\u{1b}[0m \u{1b}[33mthere\u{1b}[0m
+  |";
+    assert_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn width_override() {
+    let source = "let \u{e0a0} = branch;";
+    let input = Level::Error
+        .title("")
+        .snippet(Snippet::source(source).annotation(Level::Error.span(10..16)));
+    let expected = str![[r#"
+error
+  |
+1 | let  = branch;
+  |          ^^^^^^
+  |
+"#]];
+    let renderer = Renderer::plain().width_override('\u{e000}'..='\u{f8ff}', 2);
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn adjacent_annotations() {
+    let source = "foo.bar()";
+    let input = Level::Error.title("").snippet(
+        Snippet::source(source)
+            .annotation(Level::Error.span(0..3).label("receiver"))
+            .annotation(Level::Warning.span(3..7).label("method")),
+    );
+    let expected = str![[r#"
+error
+  |
+1 | foo.bar()
+  | ^^^ receiver
+  |    ---- method
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn level_none_title() {
+    let input = Level::None
+        .title("bare header")
+        .snippet(
+            Snippet::source("let x = 42;")
+                .origin("file/path")
+                .annotation(Level::Error.span(8..10).label("here")),
+        )
+        .footer(Level::None.title("bare footer"));
+    let expected = str![[r#"
+bare header
+ --> file/path:1:9
+  |
+1 | let x = 42;
+  |         ^^ here
+  |
+  = bare footer
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn display_columns() {
+    let source = "\tlet x = 42;";
+    let input = Level::Error.title("").snippet(
+        Snippet::source(source).annotation(
+            Level::Error
+                .span(9..11)
+                .display_columns(12..14)
+                .label("here"),
+        ),
+    );
+    let expected = str![[r#"
+error
+  |
+1 |     let x = 42;
+  |             ^^ here
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn multiline_label_at_start() {
+    let source = "fn foo() {\n    bar();\n";
+    let input = Level::Error.title("").snippet(
+        Snippet::source(source)
+            .annotation(Level::Error.span(9..21).label("unclosed delimiter"))
+            .annotation(Level::Warning.span(0..13).label("from the start")),
+    );
+    let expected = str![[r#"
+error
+  |
+1 |  / fn foo() {
+  |  /__________^ unclosed delimiter
+2 | ||     bar();
+  | ||__________^
+  | ||__- from the start
+  |
+"#]];
+    let renderer = Renderer::plain().multiline_label_at_start(true);
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn annotate_each() {
+    let source = "let (a, b, c) = (1, 2);";
+    let input = Level::Error.title("mismatched tuple sizes").snippet(
+        Snippet::source(source).annotate_each([5..6, 8..9, 11..12], Level::Warning, "binding"),
+    );
+    let expected = str![[r#"
+error: mismatched tuple sizes
+  |
+1 | let (a, b, c) = (1, 2);
+  |      - binding
+  |         - binding
+  |            - binding
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn id_url() {
+    let input = || {
+        Level::Error
+            .title("unresolved import")
+            .id("E0432")
+            .id_url("https://doc.rust-lang.org/error_codes/E0432.html")
+    };
+    let renderer = Renderer::styled();
+    let expected = "\u{1b}]8;;https://doc.rust-lang.org/error_codes/E0432.html\u{1b}\\[E0432]\u{1b}]8;;\u{1b}\\";
+    assert!(renderer.render(input()).to_string().contains(expected));
+
+    let renderer = renderer.link_level(true);
+    let expected = "\u{1b}]8;;https://doc.rust-lang.org/error_codes/E0432.html\u{1b}\\\u{1b}[1m\u{1b}[91merror[E0432]\u{1b}[0m\u{1b}]8;;\u{1b}\\";
+    assert!(renderer.render(input()).to_string().contains(expected));
+
+    // A plain renderer leaves out the link
+    let renderer = Renderer::plain().link_level(true);
+    let expected = "error[E0432]: unresolved import";
+    assert_eq!(renderer.render(input()).to_string(), expected);
+}
+
+#[test]
+fn link_paths() {
+    let input = || {
+        Level::Error.title("oops").snippet(
+            Snippet::source("let x = 42;")
+                .origin("/src/my lib.rs")
+                .annotation(Level::Error.span(8..10)),
+        )
+    };
+    let renderer = Renderer::styled().link_paths(true);
+    let expected = " \u{1b}]8;;file:///src/my%20lib.rs\u{1b}\\/src/my lib.rs\u{1b}]8;;\u{1b}\\:1:9";
+    let rendered = renderer.render(input()).to_string();
+    assert!(rendered.lines().nth(1).unwrap().ends_with(expected));
+
+    let renderer = renderer.link_paths(false);
+    let rendered = renderer.render(input()).to_string();
+    assert!(rendered
+        .lines()
+        .nth(1)
+        .unwrap()
+        .ends_with(" /src/my lib.rs:1:9"));
+
+    // A plain renderer leaves out the link
+    let renderer = Renderer::plain().link_paths(true);
+    let rendered = renderer.render(input()).to_string();
+    assert_eq!(rendered.lines().nth(1), Some(" --> /src/my lib.rs:1:9"));
+
+    // Relative paths are linked from the current directory
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source("let x = 42;")
+            .origin("src/lib.rs")
+            .annotation(Level::Error.span(8..10)),
+    );
+    let renderer = Renderer::styled().link_paths(true);
+    let rendered = renderer.render(input).to_string();
+    let header = rendered.lines().nth(1).unwrap();
+    assert!(header.contains("\u{1b}]8;;file://"));
+    assert!(header.ends_with("/src/lib.rs\u{1b}\\src/lib.rs\u{1b}]8;;\u{1b}\\:1:9"));
+}
+
+#[test]
+fn referenced_lines() {
+    let source = "fn main() {\n    let x = 42;\n    x\n}\n";
+    let input = Level::Error
+        .title("")
+        .snippet(Snippet::source(source).line_start(10))
+        .snippet(
+            Snippet::source(source)
+                .line_start(10)
+                .fold(true)
+                .annotation(Level::Error.span(20..22))
+                .annotation(Level::Error.span(32..33)),
+        )
+        .snippet(
+            Snippet::source(source)
+                .line_start(10)
+                .annotation(Level::Error.span(source.len()..source.len())),
+        );
+    assert_eq!(
+        input.referenced_lines(),
+        vec![(0, 10..14), (1, 11..13), (2, 10..15)]
+    );
+}
+
+#[test]
+fn label_leaders() {
+    let source = "let x = foo(a, b);\nlet y = x;";
+    let input = Level::Error.title("").snippet(
+        Snippet::source(source)
+            .annotation(Level::Error.span(8..17).label("not a function"))
+            .annotation(Level::Warning.span(12..13).label("first argument"))
+            .annotation(Level::Note.span(15..16).label("second argument"))
+            .annotation(Level::Info.span(27..28).label("used here")),
+    );
+    let expected = str![[r#"
+error
+  |
+1 | let x = foo(a, b);
+  |         ^^^^^^^^^
+  |         |   |  .. note: second argument
+  |         |   ..... first argument
+  |         ......... not a function
+2 | let y = x;
+  |         - info: used here
+  |
+"#]];
+    let renderer = Renderer::plain().label_leaders(true);
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn side_labels() {
+    let source = "let x = foo(a, b);";
+    let input = || {
+        Level::Error.title("").snippet(
+            Snippet::source(source)
+                .annotation(Level::Error.span(8..11).label("not a function"))
+                .annotation(Level::Warning.span(12..13).label("first argument"))
+                .annotation(Level::Note.span(15..16).label("second argument")),
+        )
+    };
+    let expected = str![[r#"
+error
+  |
+1 | let x = foo(a, b);
+  |         ^^^ ...... not a function
+  |             - .... first argument
+  |                - . note: second argument
+  |
+"#]];
+    let renderer = Renderer::plain().side_labels(true);
+    assert_data_eq!(renderer.render(input()).to_string(), expected);
+
+    let expected = str![[r#"
+error
+  |
+1 | let x = foo(a, b);
+  |         ^^^ ...... not a function
+  |             - .... first argument
+  |                - note: second argument
+  |
+"#]];
+    let renderer = renderer.term_width(40);
+    assert_data_eq!(renderer.render(input()).to_string(), expected);
+}
+
+#[test]
+fn render_origin_line() {
+    let source = "fn main() {\n    let x = 42;\n}\n";
+    let renderer = Renderer::plain();
+    let snippet = Snippet::source(source)
+        .line_start(10)
+        .origin("src/main.rs")
+        .annotation(Level::Error.span(24..26))
+        .annotation(Level::Warning.span(3..7));
+    assert_eq!(
+        renderer.render_origin_line(snippet),
+        Some("--> src/main.rs:11:13".to_owned())
+    );
+
+    let snippet = Snippet::source(source).annotation(Level::Error.span(24..26));
+    assert_eq!(renderer.render_origin_line(snippet), None);
+}
+
+#[test]
+fn min_leading_context() {
+    let source = "let result = first_function_name(argument_one, argument_two, argument_three, argument_four);";
+    let input = || {
+        Level::Error
+            .title("")
+            .snippet(Snippet::source(source).annotation(Level::Error.span(77..91).label("here")))
+    };
+    let expected = str![[r#"
+error
+  |
+1 | ...nt_three, argument_four);
+  |              ^^^^^^^^^^^^^^ here
+  |
+"#]];
+    let renderer = Renderer::plain().term_width(50);
+    assert_data_eq!(renderer.render(input()).to_string(), expected);
+
+    let expected = str![[r#"
+error
+  |
+1 | ..._two, argument_three, argument_four);
+  |                          ^^^^^^^^^^^^^^ here
+  |
+"#]];
+    let renderer = renderer.min_leading_context(25);
+    assert_data_eq!(renderer.render(input()).to_string(), expected);
+}
+
+#[test]
+fn label_icons() {
+    let source = "let x = foo(a);";
+    let input = Level::Error.title("").snippet(
+        Snippet::source(source)
+            .annotation(Level::Error.span(8..11).label("not a function"))
+            .annotation(Level::Warning.span(12..13).label("unused"))
+            .annotation(Level::Help.span(4..5)),
+    );
+    let expected = str![[r#"
+error
+  |
+1 | let x = foo(a);
+  |         ^^^ [x] not a function
+  |             - [!] unused
+  |     -
+  |
+"#]];
+    let renderer = Renderer::plain().label_icons(true);
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn render_github_actions() {
+    let source = "fn main() {\n    let x: u32 = \"42\";\n}\n";
+    let messages = [
+        Level::Error.title("mismatched types").snippet(
+            Snippet::source(source)
+                .line_start(10)
+                .origin("src/main.rs")
+                .annotation(Level::Error.span(29..33)),
+        ),
+        Level::Warning
+            .title("unused variable: `x`\n100% sure")
+            .snippet(
+                Snippet::source(source)
+                    .origin("src/a,b:c.rs")
+                    .annotation(Level::Warning.span(20..21)),
+            ),
+        Level::Error.title("borrow of moved value").snippet(
+            Snippet::source(source)
+                .origin("src/lib.rs")
+                .annotation(Level::Info.span(20..21).label("move occurs here"))
+                .annotation(Level::Error.span(29..33).label("value borrowed here")),
+        ),
+        Level::Help.title("no location"),
+        Level::Help
+            .title("no origin")
+            .snippet(Snippet::source(source).annotation(Level::Error.span(29..33))),
+    ];
+    let expected = str![[r#"
+::error file=src/main.rs,line=11,col=18::mismatched types
+::warning file=src/a%2Cb%3Ac.rs,line=2,col=9::unused variable: `x`%0A100%25 sure
+::error file=src/lib.rs,line=2,col=18::borrow of moved value
+
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render_github_actions(messages), expected);
+}
+
+#[test]
+fn content_width() {
+    let source = "let result = first_function_name(argument_one, argument_two, argument_three);";
+    let input = || {
+        Level::Error.title("").snippet(
+            Snippet::source(source)
+                .line_start(100)
+                .annotation(Level::Error.span(13..32)),
+        )
+    };
+    let expected = str![[r#"
+error
+    |
+100 | let result = first_function_name(argu...
+    |              ^^^^^^^^^^^^^^^^^^^
+    |
+"#]];
+    let renderer = Renderer::plain().content_width(40);
+    assert_data_eq!(renderer.render(input()).to_string(), expected.clone());
+
+    // The gutter is 6 columns wide
+    let renderer = Renderer::plain().term_width(46);
+    assert_data_eq!(renderer.render(input()).to_string(), expected);
+}
+
+#[test]
+fn source_has_ansi() {
+    let source = "\u{1b}[32mok\u{1b}[0m: \u{1b}[31mfailed\u{1b}[0m";
+    let input = Level::Error.title("").snippet(
+        Snippet::source(source)
+            .source_has_ansi(true)
+            .annotation(Level::Error.span(18..24).label("here")),
+    );
+    let expected = "error
+  |
+1 | \u{1b}[32mok\u{1b}[0m: \u{1b}[31mfailed\u{1b}[0m\u{1b}[0m
+  |     ^^^^^^ here
+  |";
+    let renderer = Renderer::plain();
+    assert_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn source_has_ansi_cut_right() {
+    let source =
+        "\u{1b}[32mok\u{1b}[0m: \u{1b}[31mfailed with a message that is far too long\u{1b}[0m";
+    let input = Level::Error.title("").snippet(
+        Snippet::source(source)
+            .source_has_ansi(true)
+            .annotation(Level::Error.span(18..24).label("here")),
+    );
+    let expected = "error
+  |
+1 | \u{1b}[32mok\u{1b}[0m: \u{1b}[31mfailed with a messa...\u{1b}[0m
+  |     ^^^^^^ here
+  |";
+    let renderer = Renderer::plain().term_width(30);
+    assert_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn plain_no_origin() {
+    let source = r#"let x: u32 = "42";"#;
+    let input = Level::Error.title("mismatched types").snippet(
+        Snippet::source(source).origin("src/main.rs").annotation(
+            Level::Error
+                .span(13..17)
+                .label("expected `u32`, found `&str`"),
+        ),
+    );
+    let expected = str![[r#"
+error: mismatched types
+  |
+1 | let x: u32 = "42";
+  |              ^^^^ expected `u32`, found `&str`
+  |
+"#]];
+    let renderer = Renderer::plain_no_origin();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn annotate_line() {
+    let source = "fn main() {\n    let x = 42;   \n\n}\n";
+    let input = Level::Error.title("").snippet(
+        Snippet::source(source)
+            .annotate_line(1, Level::Error, "this line")
+            .annotate_line(2, Level::Warning, "blank")
+            .annotate_line(10, Level::Warning, "missing"),
+    );
+    let expected = str![[r#"
+error
+  |
+1 | fn main() {
+2 |     let x = 42;   
+  | ^^^^^^^^^^^^^^^ this line
+3 | 
+  | - blank
+4 | }
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn group_by_label() {
+    let source = "let (a, b, c) = (x, y, z);";
+    let input = Level::Error
+        .title("")
+        .snippet(
+            Snippet::source(source)
+                .annotate_each([11..12, 5..6, 8..9], Level::Warning, "binding")
+                .annotation(Level::Error.span(17..18).label("value"))
+                .annotation(Level::Error.span(20..21))
+                .annotation(Level::Error.span(23..24)),
+        )
+        .group_by_label();
+    let expected = str![[r#"
+error
+  |
+1 | let (a, b, c) = (x, y, z);
+  |      - binding (and 2 more)
+  |                  ^ value
+  |                     ^
+  |                        ^
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+#[cfg(feature = "termcolor")]
+fn render_termcolor() {
+    let input = || {
+        Level::Error.title("oops").id("E0001").snippet(
+            Snippet::source("let x = 42;").annotation(Level::Error.span(8..10).label("here")),
+        )
+    };
+    let renderer = Renderer::plain()
+        .error(AnsiColor::Red.on_default().bold())
+        .line_no(AnsiColor::BrightBlue.on_default());
+
+    let mut out = termcolor::Buffer::no_color();
+    renderer.render_termcolor(input(), &mut out).unwrap();
+    let expected = Renderer::plain().render(input()).to_string();
+    assert_eq!(String::from_utf8(out.into_inner()).unwrap(), expected);
+
+    let mut out = termcolor::Buffer::ansi();
+    renderer.render_termcolor(input(), &mut out).unwrap();
+    let expected = "\u{1b}[0m\u{1b}[1m\u{1b}[31merror[E0001]\u{1b}[0m: oops
+\u{1b}[0m\u{1b}[38;5;12m  |\u{1b}[0m
+\u{1b}[0m\u{1b}[38;5;12m1 |\u{1b}[0m let x = 42;
+\u{1b}[0m\u{1b}[38;5;12m  |\u{1b}[0m\u{1b}[1m\u{1b}[31m         ^^\u{1b}[0m \u{1b}[0m\u{1b}[1m\u{1b}[31mhere\u{1b}[0m
+\u{1b}[0m\u{1b}[38;5;12m  |\u{1b}[0m";
+    assert_eq!(String::from_utf8(out.into_inner()).unwrap(), expected);
+}
+
+#[test]
+#[cfg(feature = "termcolor")]
+fn render_termcolor_source_has_ansi() {
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source("let \x1b[32mx\x1b[0m = 42;")
+            .source_has_ansi(true)
+            .annotation(Level::Error.span(17..19).label("here")),
+    );
+    let renderer = Renderer::plain().error(AnsiColor::Red.on_default());
+
+    let mut out = termcolor::Buffer::ansi();
+    renderer.render_termcolor(input, &mut out).unwrap();
+    let expected = "\u{1b}[0m\u{1b}[31merror\u{1b}[0m: oops
+  |
+1 | let \u{1b}[0m\u{1b}[32mx\u{1b}[0m = 42;\u{1b}[0m
+  |\u{1b}[0m\u{1b}[31m         ^^\u{1b}[0m \u{1b}[0m\u{1b}[31mhere\u{1b}[0m
+  |";
+    assert_eq!(String::from_utf8(out.into_inner()).unwrap(), expected);
+}
+
+#[test]
+fn render_to_auto_stream() {
+    let input = Level::Error
+        .title("oops\nwhoops")
+        .id("E0001")
+        .snippet(Snippet::source("let x = 42;").annotation(Level::Error.span(8..10).label("here")));
+
+    let mut out = anstream::StripStream::new(Vec::new());
+    Renderer::styled()
+        .render_to(input.clone(), &mut out)
+        .unwrap();
+    let expected = Renderer::plain().render(input).to_string();
+    assert_eq!(String::from_utf8(out.into_inner()).unwrap(), expected);
+}
+
+#[cfg(feature = "svg")]
+#[test]
+fn render_svg() {
+    let input = Level::Error.title("<oops>").snippet(
+        Snippet::source("let 名前 = 42;").annotation(Level::Error.span(4..10).label("here")),
+    );
+    let expected = str![[r#"
+<svg width="172px" height="110px" xmlns="http://www.w3.org/2000/svg">
+  <style>
+    .fg { fill: #AAAAAA }
+    .bg { background: #000000 }
+    .fg-bright-red { fill: #FF5555 }
+    .container {
+      padding: 0 10px;
+      line-height: 18px;
+    }
+    .bold { font-weight: bold; }
+    .dimmed { opacity: 0.7; }
+    .italic { font-style: italic; }
+    .underline { text-decoration: underline; }
+    .strikethrough { text-decoration: line-through; }
+    tspan {
+      font: 14px SFMono-Regular, Consolas, Liberation Mono, Menlo, monospace;
+      white-space: pre;
+      line-height: 18px;
+    }
+  </style>
+
+  <rect width="100%" height="100%" y="0" rx="4.5" class="bg" />
+
+  <text xml:space="preserve" class="container fg">
+    <tspan x="10px" y="28px"><tspan x="10.0px" class="fg-bright-red bold">error</tspan><tspan x="52.0px">: &lt;oops&gt;</tspan></tspan>
+    <tspan x="10px" y="46px"><tspan x="10.0px">  |</tspan></tspan>
+    <tspan x="10px" y="64px"><tspan x="10.0px">1 | let 名</tspan><tspan x="94.0px">前</tspan><tspan x="110.8px"> = 42;</tspan></tspan>
+    <tspan x="10px" y="82px"><tspan x="10.0px">  |</tspan><tspan x="35.2px" class="fg-bright-red bold">     ^^^^</tspan><tspan x="110.8px"> </tspan><tspan x="119.2px" class="fg-bright-red bold">here</tspan></tspan>
+    <tspan x="10px" y="100px"><tspan x="10.0px">  |</tspan></tspan>
+  </text>
+
+</svg>
+"#]];
+    let renderer = Renderer::plain().error(AnsiColor::BrightRed.on_default().bold());
+    assert_data_eq!(renderer.render_svg(input), expected);
+}
+
+#[cfg(feature = "svg")]
+#[test]
+fn render_svg_source_has_ansi() {
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source("let \x1b[32mx\x1b[0m = 42;")
+            .source_has_ansi(true)
+            .annotation(Level::Error.span(17..19).label("here")),
+    );
+    let svg = Renderer::plain().render_svg(input);
+    assert!(svg.contains(r#"<tspan x="10.0px">1 | let </tspan><tspan x="77.2px" class="fg-green">x</tspan><tspan x="85.6px"> = 42;</tspan>"#));
+}
+
+#[test]
+fn underline_len() {
+    let source = "let x = vec![\n    1, 2, 3,\n];";
+    let input = Level::Error.title("").snippet(
+        Snippet::source(source)
+            .annotation(
+                Level::Error
+                    .span(8..source.len())
+                    .underline_len(3)
+                    .label("this whole expression"),
+            )
+            .annotation(Level::Warning.span(4..5).underline_len(10).label("binding")),
+    );
+    let expected = str![[r#"
+error
+  |
+1 | let x = vec![
+  |         ^^^ this whole expression
+  |     - binding
+2 |     1, 2, 3,
+3 | ];
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn render_exploded() {
+    let source = "let x = foo(a);";
+    let input = Level::Error.title("").snippet(
+        Snippet::source(source)
+            .annotation(Level::Error.span(8..11).label("not a function"))
+            .annotation(Level::Warning.span(12..13).label("unused")),
+    );
+    let renderer = Renderer::plain();
+    let frames = renderer.render_exploded(input);
+    assert_eq!(frames.len(), 2);
+    assert_data_eq!(
+        &frames[0],
+        str![[r#"
+error
+  |
+1 | let x = foo(a);
+  |         ^^^ not a function
+  |
+"#]]
+    );
+    assert_data_eq!(
+        &frames[1],
+        str![[r#"
+error
+  |
+1 | let x = foo(a);
+  |             - unused
+  |
+"#]]
+    );
+}
+
+#[test]
+fn display_title() {
+    let input = Level::Error.title("expected\ttab \u{202E}here");
+    let renderer = Renderer::plain();
+    assert_eq!(renderer.display_title(&input), "expected    tab here");
+    let expected = str![[r#"
+error: expected    tab here
+"#]];
+    assert_data_eq!(renderer.render(input.clone()).to_string(), expected);
+
+    let renderer = Renderer::plain().tab_width(2);
+    assert_eq!(renderer.display_title(&input), "expected  tab here");
+    let expected = str![[r#"
+error: expected  tab here
+"#]];
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn multi_line_label() {
+    let source = "let x: () = 1;";
+    let label = MultiLineLabel::new()
+        .row(["expected:", "()"])
+        .row(["found:", "i32"]);
+    let input = Level::Error.title("mismatched types").snippet(
+        Snippet::source(source).annotation(
+            Level::Error
+                .span(12..13)
+                .label("types differ")
+                .multi_line_label(label.clone()),
+        ),
+    );
+    let expected = str![[r#"
+error: mismatched types
+  |
+1 | let x: () = 1;
+  |             ^ types differ
+  |             expected: ()
+  |             found:    i32
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input.clone()).to_string(), expected);
+
+    let expected = str![[r#"
+error: mismatched types
+  |
+1 | let x: () = 1;
+  |             ^ types differ, expected: (), found: i32
+  |
+"#]];
+    let renderer = Renderer::plain().term_width(25);
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn clip_to_window() {
+    let source = "fn main() {
+    let x = foo(
+        1,
+        2,
+        3,
+        4,
+        5,
+    );
+}
+";
+    let input = Level::Error.title("bad call").snippet(
+        Snippet::source(source)
+            .fold(true)
+            .clip_to_window(true)
+            .annotation(Level::Error.span(24..89).label("this call")),
+    );
+    let expected = str![[r#"
+error: bad call
+  |
+2 |     let x = foo(
+  |             ^^^^…
+3 |         1,
+...
+7 |         5,
+8 |     );
+  |    …^ this call
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn line_ending_crlf() {
+    let source = "let x = 42;";
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source(source)
+            .origin("file/path")
+            .annotation(Level::Error.span(8..10).label("here")),
+    );
+    let renderer = Renderer::plain()
+        .error(AnsiColor::Red.on_default())
+        .line_ending(LineEnding::CrLf);
+    let expected = "\u{1b}[31merror\u{1b}[0m: oops\r
+ --> file/path:1:9\r
+  |\r
+1 | let x = 42;\r
+  |\u{1b}[31m         ^^\u{1b}[0m \u{1b}[31mhere\u{1b}[0m\r
+  |";
+    assert_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn gutter_width() {
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source("let x = 42;")
+            .line_start(120)
+            .annotation(Level::Error.span(8..10).label("here")),
+    );
+    assert_eq!(Renderer::plain().gutter_width(&input), 3);
+    let renderer = Renderer::plain().anonymized_line_numbers(true);
+    assert_eq!(renderer.gutter_width(&input), 2);
+    let renderer = Renderer::plain().dual_line_numbers(true);
+    assert_eq!(renderer.gutter_width(&input), 5);
+}
+
+#[test]
+fn layout_passive() {
+    let source = "let x = foo(y);";
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source(source)
+            .origin("src/lib.rs")
+            .annotation(
+                Level::Note
+                    .span(4..5)
+                    .label("see also")
+                    .layout_passive(true),
+            )
+            .annotation(Level::Error.span(8..11).label("here")),
+    );
+    let expected = str![[r#"
+error: oops
+ --> src/lib.rs:1:9
+  |
+1 | let x = foo(y);
+  |     - note: see also
+  |         ^^^ here
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn group_by_file() {
+    let input = Level::Error
+        .title("oops")
+        .snippet(
+            Snippet::source("let x = foo();")
+                .origin("src/a.rs")
+                .annotation(Level::Error.span(4..5)),
+        )
+        .snippet(
+            Snippet::source("let y = bar();")
+                .line_start(3)
+                .origin("src/a.rs")
+                .annotation(Level::Error.span(4..5)),
+        )
+        .snippet(
+            Snippet::source("let z = baz();")
+                .origin("src/b.rs")
+                .annotation(Level::Error.span(4..5)),
+        )
+        .footer(
+            Level::Note.title("defined here").snippet(
+                Snippet::source("fn baz() {}")
+                    .line_start(5)
+                    .origin("src/b.rs")
+                    .annotation(Level::Info.span(3..6)),
+            ),
+        );
+    let expected = str![[r#"
+error: oops
+ --> src/a.rs:1:5
+  |
+1 | let x = foo();
+  |     ^
+  |
+ ::: 3:5
+  |
+3 | let y = bar();
+  |     ^
+  |
+ ::: src/b.rs:1:5
+  |
+1 | let z = baz();
+  |     ^
+  |
+note: defined here
+ ::: 5:4
+  |
+5 | fn baz() {}
+  |    ---
+  |
+"#]];
+    let renderer = Renderer::plain().group_by_file(true);
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn group_by_file_keeps_source_order() {
+    let input = Level::Error
+        .title("oops")
+        .snippet(
+            Snippet::source("let x = foo();")
+                .origin("src/a.rs")
+                .annotation(Level::Warning.span(4..5)),
+        )
+        .snippet(
+            Snippet::source("let z = baz();")
+                .origin("src/b.rs")
+                .annotation(Level::Error.span(4..5)),
+        )
+        .snippet(
+            Snippet::source("let y = bar();")
+                .line_start(3)
+                .origin("src/a.rs")
+                .annotation(Level::Warning.span(4..5)),
+        );
+    let expected = str![[r#"
+error: oops
+ --> src/a.rs:1:5
+  |
+1 | let x = foo();
+  |     -
+  |
+ ::: src/b.rs:1:5
+  |
+1 | let z = baz();
+  |     ^
+  |
+ ::: src/a.rs:3:5
+  |
+3 | let y = bar();
+  |     -
+  |
+"#]];
+    let renderer = Renderer::plain().group_by_file(true);
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn annotate_kinds() {
+    let source = r#"let x: u32 = "42";"#;
+    let input = Level::Error.title("mismatched types").snippet(
+        Snippet::source(source)
+            .annotation(
+                Level::Error
+                    .span(13..17)
+                    .label("expected `u32`, found `&str`"),
+            )
+            .annotation(Level::Info.span(7..10).label("expected due to this")),
+    );
+    let expected = str![[r#"
+error: mismatched types
+  |
+1 | let x: u32 = "42";
+  |              ^^^^ expected `u32`, found `&str` [primary]
+  |        --- info: expected due to this [context]
+  |
+"#]];
+    let renderer = Renderer::plain().annotate_kinds(true);
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn annotate_all() {
+    let source = "fn main() {
+    a();
+    b();
+    c();
+    d();
+    e();
+}
+";
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source(source)
+            .fold(true)
+            .annotate_all(Level::Error, "whole file"),
+    );
+    let expected = str![[r#"
+error: oops
+  |
+1 | / fn main() {
+2 | |     a();
+... |
+6 | |     e();
+7 | | }
+  | |_^ whole file
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source("let x = 1;\n")
+            .fold(true)
+            .annotate_all(Level::Warning, "whole file"),
+    );
+    let expected = str![[r#"
+error: oops
+  |
+1 | let x = 1;
+  | ---------- whole file
+  |
+"#]];
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn trailing_context() {
+    let source = "fn main() {
+    let x = 1;
+    foo(x);
+}
+
+fn foo() {}
+";
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source(source)
+            .fold(true)
+            .trailing_context(2)
+            .annotation(Level::Error.span(20..21).label("here")),
+    );
+    assert_eq!(input.referenced_lines(), vec![(0, 2..5)]);
+    let expected = str![[r#"
+error: oops
+  |
+2 |     let x = 1;
+  |         ^ here
+3 |     foo(x);
+4 | }
+  |
+"#]];
+    let renderer = Renderer::plain();
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn source_styler() {
+    let source = "fn a() {}
+let x = 42;
+";
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source(source)
+            .fold(true)
+            .annotation(Level::Error.span(18..20).label("here")),
+    );
+    let renderer = Renderer::plain()
+        .error(AnsiColor::Red.on_default())
+        .source_styler(|line, range| {
+            assert_eq!(range, 10..21);
+            line.match_indices("let")
+                .map(|(start, kw)| (start..start + kw.len(), AnsiColor::Magenta.on_default()))
+                .collect()
+        });
+    let expected = "\u{1b}[31merror\u{1b}[0m: oops
+  |
+2 | \u{1b}[35mlet\u{1b}[0m x = 42;
+  |\u{1b}[31m         ^^\u{1b}[0m \u{1b}[31mhere\u{1b}[0m
+  |";
+    assert_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[test]
+fn render_to() {
+    let source = "let x = 42;";
+    let input = Level::Error.title("oops").snippet(
+        Snippet::source(source)
+            .origin("file/path")
+            .annotation(Level::Error.span(8..10).label("here")),
+    );
+    for renderer in [Renderer::plain(), Renderer::styled()] {
+        let mut out = Vec::new();
+        renderer.render_to(input.clone(), &mut out).unwrap();
+        let expected = renderer.render(input.clone()).to_string();
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+}
+
+#[test]
+fn tab_width() {
+    let source = "\tlet x = 42;\n\t\tfoo(x);";
+    let input = Level::Error.title("oops:\tbad").snippet(
+        Snippet::source(source)
+            .annotation(Level::Error.span(9..11).label("here"))
+            .annotation(Level::Warning.span(15..18).label("call")),
+    );
+    let expected = str![[r#"
+error: oops:    bad
+  |
+1 |     let x = 42;
+  |             ^^ here
+2 |         foo(x);
+  |         --- call
+  |
+"#]];
+    let renderer = Renderer::plain().tab_width(4);
+    assert_data_eq!(renderer.render(input.clone()).to_string(), expected);
+
+    let expected = str![[r#"
+error: oops:        bad
+  |
+1 |         let x = 42;
+  |                 ^^ here
+2 |                 foo(x);
+  |                 --- call
+  |
+"#]];
+    let renderer = Renderer::plain().tab_width(8);
+    assert_data_eq!(renderer.render(input).to_string(), expected);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn render_json() {
+    let source = "let x: u32 = \"42\";";
+    let input = Level::Error
+        .title("mismatched types")
+        .id("E0308")
+        .snippet(
+            Snippet::source(source)
+                .origin("src/main.rs")
+                .annotation(Level::Error.span(13..17).label("expected `u32`"))
+                .annotation(Level::Info.span(7..10).label("due to this")),
+        )
+        .footer(Level::Help.title("try `42`"));
+    let renderer = Renderer::plain();
+    let expected = r#"{"level":"error","message":"mismatched types","code":"E0308","spans":[{"file_name":"src/main.rs","byte_start":13,"byte_end":17,"line_start":1,"line_end":1,"column_start":14,"column_end":18,"is_primary":true,"label":"expected `u32`"},{"file_name":"src/main.rs","byte_start":7,"byte_end":10,"line_start":1,"line_end":1,"column_start":8,"column_end":11,"is_primary":false,"label":"due to this"}],"children":[{"level":"help","message":"try `42`","code":null,"spans":[],"children":[],"rendered":null}],"rendered":"error[E0308]: mismatched types\n --> src/main.rs:1:14\n  |\n1 | let x: u32 = \"42\";\n  |              ^^^^ expected `u32`\n  |        --- info: due to this\n  |\n  = help: try `42`"}"#;
+    assert_eq!(renderer.render_json(input), expected);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn render_json_source_window() {
+    let file = "fn main() {}\nlet x: u32 = \"42\";\n";
+    let input = Level::Error.title("mismatched types").snippet(
+        Snippet::source_window(&file[13..], 13)
+            .line_start(2)
+            .origin("src/main.rs")
+            .annotation(Level::Error.span(26..30).label("expected `u32`")),
+    );
+    let renderer = Renderer::plain();
+    let expected = r#"{"level":"error","message":"mismatched types","code":null,"spans":[{"file_name":"src/main.rs","byte_start":26,"byte_end":30,"line_start":2,"line_end":2,"column_start":14,"column_end":18,"is_primary":true,"label":"expected `u32`"}],"children":[],"rendered":"error: mismatched types\n --> src/main.rs:2:14\n  |\n2 | let x: u32 = \"42\";\n  |              ^^^^ expected `u32`\n  |"}"#;
+    assert_eq!(renderer.render_json(input), expected);
+}